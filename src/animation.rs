@@ -0,0 +1,353 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::overrides::Override;
+
+// keyframed scene parameters, each track animates the value at an override path
+// e.g. `camera.position`, `lights.0.intensity` or `materials.ivory.diffuse_color`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Animation {
+    pub frame_start: usize,
+    pub frame_end: usize,
+    pub tracks: Vec<Track>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Track {
+    pub path: String,
+    #[serde(default)]
+    pub interpolation: Interpolation,
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Interpolation {
+    #[default]
+    Linear,
+    // smooth curve going through all the keyframes (catmull-rom tangents)
+    Bezier,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub frame: f32,
+    pub value: Value,
+}
+
+impl Animation {
+    pub fn frames(&self) -> impl Iterator<Item = usize> {
+        self.frame_start..=self.frame_end
+    }
+
+    pub fn overrides_at(&self, frame: f32) -> Vec<Override> {
+        self.tracks
+            .iter()
+            .filter_map(|track| {
+                let mut value = track.value_at(frame)?;
+                round_integers(&track.path, &mut value);
+                Some(Override::new(&track.path, value))
+            })
+            .collect()
+    }
+}
+
+// the fields of the scene that are integers, an interpolated value is rounded for them
+const INTEGER_PATHS: [&str; 9] = [
+    "frame_width",
+    "frame_height",
+    "max_reflect_depth",
+    "samples_per_pixel",
+    "tiles.size",
+    "camera.aperture_blades",
+    "denoise.iterations",
+    "adaptive_sampling.min_samples",
+    "adaptive_sampling.max_samples",
+];
+
+// value is the one at path, which may hold integer fields, e.g. the whole camera
+fn round_integers(path: &str, value: &mut Value) {
+    for integer_path in INTEGER_PATHS.iter() {
+        let rest = if *integer_path == path {
+            ""
+        } else {
+            match integer_path
+                .strip_prefix(path)
+                .and_then(|rest| rest.strip_prefix('.'))
+            {
+                Some(rest) => rest,
+                None => continue,
+            }
+        };
+        let field = rest
+            .split('.')
+            .filter(|key| !key.is_empty())
+            .try_fold(&mut *value, |value, key| value.get_mut(key));
+        if let Some(field) = field {
+            if let Some(number) = field.as_f64() {
+                *field = Value::from(number.round() as i64);
+            }
+        }
+    }
+}
+
+impl Track {
+    pub fn value_at(&self, frame: f32) -> Option<Value> {
+        let keyframes = &self.keyframes;
+        let first = keyframes.first()?;
+        let last = keyframes.last()?;
+        if frame <= first.frame {
+            return Some(first.value.clone());
+        }
+        if frame >= last.frame {
+            return Some(last.value.clone());
+        }
+
+        // keyframes[i].frame < frame < keyframes[i + 1].frame
+        let i = keyframes
+            .windows(2)
+            .position(|pair| frame < pair[1].frame)
+            .expect("frame is between the first and the last keyframes");
+        let t = (frame - keyframes[i].frame) / (keyframes[i + 1].frame - keyframes[i].frame);
+
+        let weights = match self.interpolation {
+            Interpolation::Linear => vec![(1.0 - t, i), (t, i + 1)],
+            Interpolation::Bezier => {
+                // cubic bezier with p1 = v[i] + (v[i+1] - v[i-1]) / 6
+                // and p2 = v[i+1] - (v[i+2] - v[i]) / 6, expressed as weights on the keyframes
+                let prev = i.saturating_sub(1);
+                let next = (i + 2).min(keyframes.len() - 1);
+                let b0 = (1.0 - t) * (1.0 - t) * (1.0 - t);
+                let b1 = 3.0 * (1.0 - t) * (1.0 - t) * t;
+                let b2 = 3.0 * (1.0 - t) * t * t;
+                let b3 = t * t * t;
+                vec![
+                    (-b1 / 6.0, prev),
+                    (b0 + b1 + b2 / 6.0, i),
+                    (b1 / 6.0 + b2 + b3, i + 1),
+                    (-b2 / 6.0, next),
+                ]
+            }
+        };
+
+        let weighted_values: Vec<(f32, &Value)> = weights
+            .iter()
+            .map(|(weight, index)| (*weight, &keyframes[*index].value))
+            .collect();
+
+        // values that can't be interpolated (strings, ...) are held until the next keyframe
+        Some(weighted_sum(&weighted_values).unwrap_or_else(|| keyframes[i].value.clone()))
+    }
+}
+
+// weighted sum of numbers, or of arrays/objects of numbers with the same layout
+fn weighted_sum(values: &[(f32, &Value)]) -> Option<Value> {
+    match values[0].1 {
+        Value::Number(_) => {
+            // integer keyframes glide too, the integer fields are rounded once the path is known
+            let sum = values
+                .iter()
+                .map(|(weight, value)| Some(*weight as f64 * value.as_f64()?))
+                .sum::<Option<f64>>()?;
+            Some(Value::from(sum))
+        }
+        Value::Array(first) => (0..first.len())
+            .map(|index| {
+                let elements = values
+                    .iter()
+                    .map(|(weight, value)| Some((*weight, value.as_array()?.get(index)?)))
+                    .collect::<Option<Vec<_>>>()?;
+                weighted_sum(&elements)
+            })
+            .collect::<Option<Vec<Value>>>()
+            .map(Value::Array),
+        Value::Object(first) => first
+            .keys()
+            .map(|key| {
+                let fields = values
+                    .iter()
+                    .map(|(weight, value)| Some((*weight, value.as_object()?.get(key)?)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some((key.clone(), weighted_sum(&fields)?))
+            })
+            .collect::<Option<Map<String, Value>>>()
+            .map(Value::Object),
+        _ => None,
+    }
+}
+
+// replaces `%d` or `%0Nd` in pattern by the frame number,
+// the frame number is appended to the file stem if there is no such placeholder
+pub fn frame_file_name(pattern: &str, frame: usize) -> String {
+    if let Some(start) = pattern.find('%') {
+        if let Some(end) = pattern[start..].find('d').map(|len| start + len) {
+            let width = &pattern[start + 1..end];
+            if width.chars().all(|c| c.is_ascii_digit()) {
+                return format!(
+                    "{}{:0width$}{}",
+                    &pattern[..start],
+                    frame,
+                    &pattern[end + 1..],
+                    width = width.parse().unwrap_or(0)
+                );
+            }
+        }
+    }
+
+    suffixed_file_name(pattern, &format!("{:04}", frame))
+}
+
+// out.png -> out_SUFFIX.png, the dots of the directories aren't extensions
+pub fn suffixed_file_name(path: &str, suffix: &str) -> String {
+    let file_path = Path::new(path);
+    let file_name = match (file_path.file_stem(), file_path.extension()) {
+        (Some(stem), Some(extension)) => format!(
+            "{}_{}.{}",
+            stem.to_string_lossy(),
+            suffix,
+            extension.to_string_lossy()
+        ),
+        (Some(stem), None) => format!("{}_{}", stem.to_string_lossy(), suffix),
+        (None, _) => return format!("{}_{}", path, suffix),
+    };
+    file_path
+        .with_file_name(file_name)
+        .to_string_lossy()
+        .to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    fn make_track(interpolation: Interpolation, keyframes: Vec<(f32, Value)>) -> Track {
+        Track {
+            path: String::from("camera.position"),
+            interpolation,
+            keyframes: keyframes
+                .into_iter()
+                .map(|(frame, value)| Keyframe { frame, value })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_linear() {
+        let track = make_track(
+            Interpolation::Linear,
+            vec![
+                (1.0, json!({"x": 0.0, "y": 0.0, "z": 0.0})),
+                (11.0, json!({"x": 10.0, "y": -10.0, "z": 0.0})),
+            ],
+        );
+
+        assert_eq!(
+            track.value_at(0.0),
+            Some(json!({"x": 0.0, "y": 0.0, "z": 0.0}))
+        );
+        assert_eq!(
+            track.value_at(6.0),
+            Some(json!({"x": 5.0, "y": -5.0, "z": 0.0}))
+        );
+        assert_eq!(
+            track.value_at(20.0),
+            Some(json!({"x": 10.0, "y": -10.0, "z": 0.0}))
+        );
+    }
+
+    #[test]
+    fn test_bezier() {
+        let track = make_track(
+            Interpolation::Bezier,
+            vec![(0.0, json!(0.0)), (10.0, json!(10.0)), (20.0, json!(0.0))],
+        );
+
+        // goes through the keyframes
+        assert_eq!(track.value_at(10.0), Some(json!(10.0)));
+        // and overshoots the straight line towards the peak
+        let value = track.value_at(7.5).unwrap().as_f64().unwrap();
+        assert!(value > 7.5 && value < 10.0);
+    }
+
+    #[test]
+    fn test_integer_keyframes() {
+        let track = |path: &str, keyframes| Track {
+            path: path.to_string(),
+            ..make_track(Interpolation::Linear, keyframes)
+        };
+        let animation = Animation {
+            frame_start: 0,
+            frame_end: 10,
+            tracks: vec![
+                track(
+                    "camera",
+                    vec![
+                        (0.0, json!({ "aperture_blades": 5, "aperture_radius": 0 })),
+                        (10.0, json!({ "aperture_blades": 8, "aperture_radius": 1 })),
+                    ],
+                ),
+                track(
+                    "camera.position",
+                    vec![(0.0, json!([0, 0, 0])), (10.0, json!([5, 2, -4]))],
+                ),
+                track("frame_width", vec![(0.0, json!(100)), (10.0, json!(105))]),
+            ],
+        };
+
+        let mut scene = json!({});
+        for scene_override in animation.overrides_at(3.0) {
+            scene_override.apply(&mut scene).unwrap();
+        }
+        // the camera glides between whole positions
+        let position: Vec<f64> =
+            serde_json::from_value(scene["camera"]["position"].clone()).unwrap();
+        for (value, expected) in position.iter().zip([1.5, 0.6, -1.2]) {
+            assert!((value - expected).abs() < 1e-6);
+        }
+        assert!((scene["camera"]["aperture_radius"].as_f64().unwrap() - 0.3).abs() < 1e-6);
+        // but the integer fields stay integers
+        assert_eq!(scene["frame_width"], json!(102));
+        assert_eq!(scene["camera"]["aperture_blades"], json!(6));
+    }
+
+    #[test]
+    fn test_hold_non_numeric_values() {
+        let track = make_track(
+            Interpolation::Linear,
+            vec![(0.0, json!("ivory")), (10.0, json!("mirror"))],
+        );
+
+        assert_eq!(track.value_at(9.0), Some(json!("ivory")));
+        assert_eq!(track.value_at(10.0), Some(json!("mirror")));
+    }
+
+    #[test]
+    fn test_frame_file_name() {
+        assert_eq!(frame_file_name("frame_%04d.png", 7), "frame_0007.png");
+        assert_eq!(frame_file_name("frame_%d.png", 42), "frame_42.png");
+        assert_eq!(frame_file_name("out.png", 7), "out_0007.png");
+        assert_eq!(
+            frame_file_name("renders.v2/out.png", 7),
+            "renders.v2/out_0007.png"
+        );
+        assert_eq!(frame_file_name("renders.v2/out", 7), "renders.v2/out_0007");
+    }
+
+    #[test]
+    fn test_suffixed_file_name() {
+        assert_eq!(suffixed_file_name("out.png", "left"), "out_left.png");
+        assert_eq!(suffixed_file_name("out", "left"), "out_left");
+        assert_eq!(
+            suffixed_file_name("renders.v2/out.png", "right"),
+            "renders.v2/out_right.png"
+        );
+        assert_eq!(
+            suffixed_file_name("renders.v2/out", "left"),
+            "renders.v2/out_left"
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::shapes::shape::Ray;
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Camera {
+    pub position: Vector3<f32>,
+    pub direction: Vector3<f32>,
+    pub up: Vector3<f32>,
+
+    // thin lens depth of field, a radius of 0 is a pinhole camera where everything is in focus
+    pub aperture_radius: f32,
+    pub focus_distance: f32,
+    // number of aperture blades for polygonal bokeh, 0 for a round aperture
+    pub aperture_blades: usize,
+    pub aperture_rotation_in_degrees: f32,
+
+    pub projection: Projection,
+    pub stereo: Option<Stereo>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Projection {
+    // uses the fov of the scene
+    #[default]
+    Perspective,
+    // parallel rays, view_width is the width of the frame in scene units
+    Orthographic {
+        view_width: f32,
+    },
+    // equidistant fisheye, the circle of the image fits the frame height
+    Fisheye {
+        fov_in_degrees: f32,
+    },
+    // full 360x180 panorama
+    Equirectangular,
+}
+
+// stereo camera rig, the eyes are interocular_distance apart along the camera right axis
+// with the equirectangular projection the eyes turn with the view (omni-directional stereo)
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stereo {
+    pub interocular_distance: f32,
+    #[serde(default)]
+    pub convergence: Convergence,
+    // distance at which the eyes converge for toe_in and off_axis
+    #[serde(default = "default_convergence_distance")]
+    pub convergence_distance: f32,
+    pub layout: StereoLayout,
+}
+
+fn default_convergence_distance() -> f32 {
+    10.0
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Convergence {
+    // both eyes look straight ahead
+    #[default]
+    Parallel,
+    // the eyes are rotated towards the convergence point
+    ToeIn,
+    // the eyes look straight ahead with their frustum shifted towards the convergence point
+    OffAxis,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StereoLayout {
+    // left eye on the left half
+    SideBySide,
+    // left eye on the top half
+    TopBottom,
+    // a single eye on the whole frame
+    Left,
+    Right,
+    // one image per eye, see Camera::views
+    Separate,
+}
+
+impl Default for Camera {
+    // looking down -z from the origin, as the scenes were rendered before cameras existed
+    fn default() -> Self {
+        Self {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            direction: Vector3::new(0.0, 0.0, -1.0),
+            up: Vector3::new(0.0, 1.0, 0.0),
+            aperture_radius: 0.0,
+            focus_distance: 10.0,
+            aperture_blades: 0,
+            aperture_rotation_in_degrees: 0.0,
+            projection: Projection::Perspective,
+            stereo: None,
+        }
+    }
+}
+
+impl Camera {
+    pub fn new(position: Vector3<f32>, direction: Vector3<f32>, up: Vector3<f32>) -> Self {
+        Self {
+            position,
+            direction,
+            up,
+            ..Default::default()
+        }
+    }
+
+    // the cameras to render, one per eye with the separate stereo layout
+    pub fn views(&self) -> Vec<(Option<&'static str>, Camera)> {
+        match self.stereo {
+            Some(stereo) if stereo.layout == StereoLayout::Separate => {
+                [("left", StereoLayout::Left), ("right", StereoLayout::Right)]
+                    .iter()
+                    .map(|(name, layout)| {
+                        let camera = Camera {
+                            stereo: Some(Stereo {
+                                layout: *layout,
+                                ..stereo
+                            }),
+                            ..*self
+                        };
+                        (Some(*name), camera)
+                    })
+                    .collect()
+            }
+            _ => vec![(None, *self)],
+        }
+    }
+
+    // x and y are framebuffer coordinates, returns None if the projection doesn't cover the pixel
+    pub fn primary_ray<R: Rng>(
+        &self,
+        x: f32,
+        y: f32,
+        frame_size: (usize, usize),
+        fov: f32,
+        time: f32,
+        rng: &mut R,
+    ) -> Option<Ray> {
+        let (mut x, mut y) = (x, y);
+        let (mut width, mut height) = (frame_size.0 as f32, frame_size.1 as f32);
+
+        // -0.5 for the left eye, 0.5 for the right eye
+        // the framebuffer is stored upside down so the left/top half of the image is its second half
+        let mut eye = 0.0;
+        if let Some(stereo) = &self.stereo {
+            match stereo.layout {
+                StereoLayout::SideBySide => {
+                    width /= 2.0;
+                    eye = if x < width { 0.5 } else { -0.5 };
+                    x %= width;
+                }
+                StereoLayout::TopBottom => {
+                    height /= 2.0;
+                    eye = if y < height { 0.5 } else { -0.5 };
+                    y %= height;
+                }
+                // rendered as the left eye when not split by Camera::views
+                StereoLayout::Left | StereoLayout::Separate => eye = -0.5,
+                StereoLayout::Right => eye = 0.5,
+            }
+        }
+        let eye_distance = eye
+            * self
+                .stereo
+                .map_or(0.0, |stereo| stereo.interocular_distance);
+
+        // screen coordinates in [-1, 1], x to the right and y up
+        let screen_x = 1.0 - 2.0 * x / width;
+        let screen_y = 2.0 * y / height - 1.0;
+        let aspect_ratio = width / height;
+
+        match self.projection {
+            Projection::Perspective => {
+                // the scene fov is negative, which flips the framebuffer coordinates
+                let direction = Vector3::new(
+                    (2.0 * x / width - 1.0) * (fov / 2.0).tan() * width / height,
+                    -(2.0 * y / height - 1.0) * (fov / 2.0).tan(),
+                    -1.0,
+                );
+                let direction = match self.stereo {
+                    Some(Stereo {
+                        convergence: Convergence::ToeIn,
+                        convergence_distance,
+                        ..
+                    }) => {
+                        let angle = eye_distance.atan2(convergence_distance);
+                        Vector3::new(
+                            direction.x * angle.cos() + direction.z * angle.sin(),
+                            direction.y,
+                            -direction.x * angle.sin() + direction.z * angle.cos(),
+                        )
+                    }
+                    Some(Stereo {
+                        convergence: Convergence::OffAxis,
+                        convergence_distance,
+                        ..
+                    }) => direction - Vector3::new(eye_distance / convergence_distance, 0.0, 0.0),
+                    _ => direction,
+                };
+                let eye_offset = Vector3::new(eye_distance, 0.0, 0.0);
+                Some(self.lens_ray(eye_offset, direction, time, rng))
+            }
+            Projection::Orthographic { view_width } => {
+                let origin = Vector3::new(
+                    screen_x * view_width / 2.0,
+                    screen_y * view_width / 2.0 / aspect_ratio,
+                    0.0,
+                ) + Vector3::new(eye_distance, 0.0, 0.0);
+                Some(Ray::new_at_time(
+                    self.position + self.to_world(origin),
+                    self.to_world(Vector3::new(0.0, 0.0, -1.0)).normalize(),
+                    time,
+                ))
+            }
+            Projection::Fisheye { fov_in_degrees } => {
+                let (fisheye_x, fisheye_y) = (screen_x * aspect_ratio, screen_y);
+                let radius = (fisheye_x * fisheye_x + fisheye_y * fisheye_y).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+                let theta = radius * fov_in_degrees.to_radians() / 2.0;
+                let phi = fisheye_y.atan2(fisheye_x);
+                let direction = Vector3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                );
+                let eye_offset = Vector3::new(eye_distance, 0.0, 0.0);
+                Some(self.lens_ray(eye_offset, direction, time, rng))
+            }
+            Projection::Equirectangular => {
+                let longitude = screen_x * PI;
+                let latitude = screen_y * PI / 2.0;
+                let direction = Vector3::new(
+                    latitude.cos() * longitude.sin(),
+                    latitude.sin(),
+                    -latitude.cos() * longitude.cos(),
+                );
+                // the eyes are on the circle tangent to the view direction
+                let eye_offset = Vector3::new(longitude.cos(), 0.0, longitude.sin()) * eye_distance;
+                Some(Ray::new_at_time(
+                    self.position + self.to_world(eye_offset),
+                    self.to_world(direction).normalize(),
+                    time,
+                ))
+            }
+        }
+    }
+
+    // origin and direction are in camera space
+    fn lens_ray<R: Rng>(
+        &self,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        time: f32,
+        rng: &mut R,
+    ) -> Ray {
+        if self.aperture_radius <= 0.0 {
+            return Ray::new_at_time(
+                self.position + self.to_world(origin),
+                self.to_world(direction).normalize(),
+                time,
+            );
+        }
+
+        // every ray going through the lens meets on the focus plane
+        let focus_point = origin + direction * (self.focus_distance / -direction.z);
+        let lens_point = origin + (self.sample_aperture(rng) * self.aperture_radius).extend(0.0);
+
+        Ray::new_at_time(
+            self.position + self.to_world(lens_point),
+            self.to_world(focus_point - lens_point).normalize(),
+            time,
+        )
+    }
+
+    // uniform point on the unit aperture
+    fn sample_aperture<R: Rng>(&self, rng: &mut R) -> Vector2<f32> {
+        let rotation = self.aperture_rotation_in_degrees * (PI / 180.0);
+        if self.aperture_blades < 3 {
+            let radius = rng.gen::<f32>().sqrt();
+            let angle = 2.0 * PI * rng.gen::<f32>() + rotation;
+            return Vector2::new(angle.cos(), angle.sin()) * radius;
+        }
+
+        // pick one of the triangles between the center and two consecutive blade corners
+        let blade_angle = 2.0 * PI / self.aperture_blades as f32;
+        let blade = rng.gen_range(0..self.aperture_blades) as f32;
+        let corner_1 = blade * blade_angle + rotation;
+        let corner_2 = corner_1 + blade_angle;
+
+        let (mut u, mut v) = (rng.gen::<f32>(), rng.gen::<f32>());
+        if u + v > 1.0 {
+            u = 1.0 - u;
+            v = 1.0 - v;
+        }
+        Vector2::new(corner_1.cos(), corner_1.sin()) * u
+            + Vector2::new(corner_2.cos(), corner_2.sin()) * v
+    }
+
+    // converts a direction from camera space (x right, y up, looking down -z) to world space
+    pub fn to_world(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let forward = self.direction.normalize();
+        let right = forward.cross(self.up).normalize();
+        let up = right.cross(forward);
+
+        right * direction.x + up * direction.y - forward * direction.z
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_default_camera_is_identity() {
+        let camera = Camera::default();
+        let direction = Vector3::new(0.3, -0.2, -1.0);
+
+        assert!((camera.to_world(direction) - direction).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn test_thin_lens_focus() {
+        let camera = Camera {
+            aperture_radius: 0.5,
+            focus_distance: 4.0,
+            aperture_blades: 6,
+            ..Default::default()
+        };
+        let mut rng = rand::thread_rng();
+
+        // rays start on the lens and all go through the same point of the focus plane
+        let direction = Vector3::new(0.25, 0.1, -1.0);
+        for _ in 0..100 {
+            let ray = camera.lens_ray(Vector3::new(0.0, 0.0, 0.0), direction, 0.0, &mut rng);
+            assert!(ray.origin.z == 0.0 && ray.origin.magnitude() <= 0.5 + 1e-6);
+
+            let focus_point = ray.origin + ray.direction * (-4.0 - ray.origin.z) / ray.direction.z;
+            assert!((focus_point - direction * 4.0).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn test_projections() {
+        let mut rng = rand::thread_rng();
+        let forward = Vector3::new(0.0, 0.0, -1.0);
+
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic { view_width: 4.0 },
+            Projection::Fisheye {
+                fov_in_degrees: 180.0,
+            },
+            Projection::Equirectangular,
+        ] {
+            let camera = Camera {
+                projection,
+                ..Default::default()
+            };
+
+            // the center of the frame looks forward
+            let ray = camera
+                .primary_ray(50.0, 25.0, (100, 50), -1.0, 0.0, &mut rng)
+                .unwrap();
+            assert!((ray.direction - forward).magnitude() < 1e-5);
+            assert!(ray.origin.magnitude() < 1e-5);
+        }
+
+        // the corners of the frame are out of the fisheye circle
+        let camera = Camera {
+            projection: Projection::Fisheye {
+                fov_in_degrees: 180.0,
+            },
+            ..Default::default()
+        };
+        assert!(camera
+            .primary_ray(0.0, 0.0, (100, 50), -1.0, 0.0, &mut rng)
+            .is_none());
+    }
+
+    #[test]
+    fn test_stereo_convergence() {
+        let mut rng = rand::thread_rng();
+        let convergence_point = Vector3::new(0.0, 0.0, -8.0);
+
+        for convergence in [Convergence::ToeIn, Convergence::OffAxis] {
+            let camera = Camera {
+                stereo: Some(Stereo {
+                    interocular_distance: 1.0,
+                    convergence,
+                    convergence_distance: 8.0,
+                    layout: StereoLayout::Separate,
+                }),
+                ..Default::default()
+            };
+
+            // the center rays of both eyes go through the convergence point
+            let views = camera.views();
+            assert_eq!(views.len(), 2);
+            for (_, eye) in views {
+                let ray = eye
+                    .primary_ray(50.0, 25.0, (100, 50), -1.0, 0.0, &mut rng)
+                    .unwrap();
+                assert!((ray.origin.x.abs() - 0.5).abs() < 1e-5);
+                let to_convergence_point = (convergence_point - ray.origin).normalize();
+                assert!((ray.direction - to_convergence_point).magnitude() < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn test_to_world() {
+        // looking down +x
+        let camera = Camera::new(
+            Vector3::new(1.0, 2.0, 3.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, 1.0, 0.0),
+        );
+
+        let forward = camera.to_world(Vector3::new(0.0, 0.0, -1.0));
+        assert!((forward - Vector3::new(1.0, 0.0, 0.0)).magnitude() < 1e-6);
+
+        let right = camera.to_world(Vector3::new(1.0, 0.0, 0.0));
+        assert!((right - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-6);
+    }
+}
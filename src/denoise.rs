@@ -0,0 +1,141 @@
+use cgmath::InnerSpace;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::scene::{FrameBuffer, Pixel};
+
+// 5x5 B3 spline kernel used by the a-trous wavelet filter
+const KERNEL: [f32; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// edge-avoiding a-trous wavelet denoiser
+// (Dammertz et al. "Edge-Avoiding A-Trous Wavelet Transform for fast Global Illumination Filtering")
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DenoiseOptions {
+    pub iterations: usize,
+    pub color_phi: f32,
+    pub normal_phi: f32,
+    pub albedo_phi: f32,
+}
+
+impl Default for DenoiseOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 5,
+            color_phi: 0.5,
+            normal_phi: 0.1,
+            albedo_phi: 0.1,
+        }
+    }
+}
+
+impl DenoiseOptions {
+    pub fn denoise(&self, framebuffer: &FrameBuffer) -> Vec<Pixel> {
+        (0..self.iterations).fold(framebuffer.buffer.clone(), |colors, iteration| {
+            // the color weight gets tighter as the filter gets wider
+            let color_phi = self.color_phi / (1 << iteration) as f32;
+            let step = 1 << iteration;
+
+            (0..framebuffer.height)
+                .into_par_iter()
+                .flat_map(|y| {
+                    (0..framebuffer.width)
+                        .map(|x| self.filter_pixel(framebuffer, &colors, x, y, step, color_phi))
+                        .collect::<Vec<Pixel>>()
+                })
+                .collect()
+        })
+    }
+
+    fn filter_pixel(
+        &self,
+        framebuffer: &FrameBuffer,
+        colors: &[Pixel],
+        x: usize,
+        y: usize,
+        step: usize,
+        color_phi: f32,
+    ) -> Pixel {
+        let index = y * framebuffer.width + x;
+        let color = colors[index];
+        let normal = framebuffer.normal[index];
+        let albedo = framebuffer.albedo[index];
+
+        let mut sum = Pixel::new(0.0, 0.0, 0.0);
+        let mut weight_sum = 0.0;
+
+        for (ky, kernel_y) in KERNEL.iter().enumerate() {
+            for (kx, kernel_x) in KERNEL.iter().enumerate() {
+                let sample_x = x as isize + (kx as isize - 2) * step as isize;
+                let sample_y = y as isize + (ky as isize - 2) * step as isize;
+                let sample_x = num::clamp(sample_x, 0, framebuffer.width as isize - 1) as usize;
+                let sample_y = num::clamp(sample_y, 0, framebuffer.height as isize - 1) as usize;
+                let sample_index = sample_y * framebuffer.width + sample_x;
+
+                let color_weight = edge_stop(colors[sample_index] - color, color_phi);
+                let normal_weight =
+                    edge_stop(framebuffer.normal[sample_index] - normal, self.normal_phi);
+                let albedo_weight =
+                    edge_stop(framebuffer.albedo[sample_index] - albedo, self.albedo_phi);
+
+                let weight = kernel_x * kernel_y * color_weight * normal_weight * albedo_weight;
+                sum += colors[sample_index] * weight;
+                weight_sum += weight;
+            }
+        }
+
+        // the center sample always has a weight > 0 so weight_sum can't be 0
+        sum / weight_sum
+    }
+}
+
+fn edge_stop(diff: Pixel, phi: f32) -> f32 {
+    (-diff.magnitude2() / (phi * phi).max(1e-6)).exp().min(1.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_framebuffer(buffer: Vec<Pixel>, width: usize, height: usize) -> FrameBuffer {
+        FrameBuffer {
+            width,
+            height,
+            albedo: vec![Pixel::new(1.0, 1.0, 1.0); buffer.len()],
+            normal: vec![Pixel::new(0.0, 0.0, 1.0); buffer.len()],
+            buffer,
+        }
+    }
+
+    #[test]
+    fn test_denoise_flat_image() {
+        let framebuffer = make_framebuffer(vec![Pixel::new(0.2, 0.4, 0.6); 16 * 16], 16, 16);
+
+        let denoised = DenoiseOptions::default().denoise(&framebuffer);
+
+        for pixel in denoised {
+            assert!((pixel - Pixel::new(0.2, 0.4, 0.6)).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_denoise_keeps_normal_edges() {
+        // left half faces the camera, right half faces up, with a noisy pixel on the left
+        let mut framebuffer = make_framebuffer(vec![Pixel::new(0.5, 0.5, 0.5); 16 * 16], 16, 16);
+        for y in 0..16 {
+            for x in 8..16 {
+                framebuffer.buffer[y * 16 + x] = Pixel::new(0.0, 0.0, 0.0);
+                framebuffer.normal[y * 16 + x] = Pixel::new(0.0, 1.0, 0.0);
+            }
+        }
+        framebuffer.buffer[8 * 16 + 3] = Pixel::new(1.0, 1.0, 1.0);
+
+        let denoised = DenoiseOptions::default().denoise(&framebuffer);
+
+        // the noisy pixel got smoothed out
+        assert!(denoised[8 * 16 + 3].x < 0.9);
+        // but nothing leaked across the normal edge
+        assert!(denoised[8 * 16 + 8].x < 1e-3);
+        assert!(denoised[8 * 16 + 7].x > 0.45);
+    }
+}
@@ -0,0 +1,496 @@
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::camera::Camera;
+use crate::overrides::Override;
+use crate::render_options::{ProgressTracker, RenderOptions};
+use crate::scene::{FrameBuffer, Scene, SceneError, SceneFile};
+use crate::tiles::{FinishedTile, Region, Tile};
+
+// everything a worker needs to build the same scene as the coordinator,
+// the scene file (and the files it uses) must be reachable from the worker at the same path
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub scene: String,
+    pub overrides: Vec<Override>,
+    pub frame: Option<f32>,
+    pub camera: Camera,
+    pub region: Option<Region>,
+}
+
+// the messages are sent as json lines
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Job(Job),
+    Tile { index: usize },
+    // the frame is finished, the worker may connect again for the next one
+    Done,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Ready { threads: usize },
+    Tile(FinishedTile),
+    Error { message: String },
+}
+
+#[derive(Debug)]
+pub enum DistributedError {
+    Io(io::Error),
+    Scene(SceneError),
+    Protocol(String),
+    Disconnected,
+    TimedOut(Duration),
+}
+
+impl fmt::Display for DistributedError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DistributedError::Io(err) => write!(f, "connection error: {}", err),
+            DistributedError::Scene(err) => write!(f, "{}", err),
+            DistributedError::Protocol(err) => write!(f, "protocol error: {}", err),
+            DistributedError::Disconnected => write!(f, "connection closed"),
+            DistributedError::TimedOut(timeout) => {
+                write!(f, "no tile received for {}s", timeout.as_secs_f32())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DistributedError {}
+
+impl From<io::Error> for DistributedError {
+    fn from(err: io::Error) -> Self {
+        DistributedError::Io(err)
+    }
+}
+
+impl From<SceneError> for DistributedError {
+    fn from(err: SceneError) -> Self {
+        DistributedError::Scene(err)
+    }
+}
+
+// how long a worker keeps trying to reach the coordinator
+const FIRST_CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+const NEXT_CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+// how long the coordinator waits for the next tile of a worker before giving its tiles to the others
+pub const DEFAULT_WORKER_TIMEOUT: Duration = Duration::from_secs(300);
+
+// serves the tiles of the frames to the workers connecting to it
+pub struct Coordinator {
+    listener: TcpListener,
+    worker_timeout: Duration,
+}
+
+impl Coordinator {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        // polled so the coordinator can stop accepting once the frame is done
+        listener.set_nonblocking(true)?;
+        Ok(Self {
+            listener,
+            worker_timeout: DEFAULT_WORKER_TIMEOUT,
+        })
+    }
+
+    // a worker that hangs is dropped once it has sent nothing for this long while it has tiles to render,
+    // it must be longer than the slowest tile takes to render
+    pub fn with_worker_timeout(mut self, worker_timeout: Duration) -> Self {
+        self.worker_timeout = worker_timeout;
+        self
+    }
+
+    pub fn local_address(&self) -> io::Result<String> {
+        Ok(self.listener.local_addr()?.to_string())
+    }
+
+    // waits for workers and hands them the tiles of the scene until they are all rendered,
+    // the tiles of a worker that fails go back in the queue for the others
+    pub fn render(
+        &self,
+        scene: &Scene,
+        job: &Job,
+        options: &RenderOptions,
+    ) -> Result<FrameBuffer, DistributedError> {
+        let tiles = scene.tile_list();
+        let queue = TileQueue {
+            state: Mutex::new(QueueState {
+                pending: (0..tiles.len()).collect(),
+                remaining: tiles.len(),
+                framebuffer: FrameBuffer::new(scene.frame_width, scene.frame_height),
+            }),
+            progress: options.progress_tracker(0, Some(tiles.len())),
+            options,
+        };
+
+        info!(
+            "rendering, waiting for workers on {}...",
+            self.local_address()?
+        );
+        thread::scope(|s| {
+            while !queue.is_done() {
+                match self.listener.accept() {
+                    Ok((stream, address)) => {
+                        stream.set_nonblocking(false)?;
+                        stream.set_read_timeout(Some(self.worker_timeout))?;
+                        let (queue, tiles) = (&queue, &tiles);
+                        s.spawn(move || {
+                            if let Err(err) = serve_worker(stream, queue, tiles, job) {
+                                warn!(
+                                    "worker {} failed: {}, its tiles go to the other workers",
+                                    address, err
+                                );
+                            }
+                        });
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                        thread::sleep(Duration::from_millis(20))
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            Ok::<(), io::Error>(())
+        })?;
+        if options.is_cancelled() {
+            return Err(SceneError::Cancelled.into());
+        }
+        info!("rendering done!");
+
+        let mut framebuffer = queue.state.into_inner().unwrap().framebuffer;
+        scene.run_denoiser(&mut framebuffer);
+
+        Ok(framebuffer)
+    }
+}
+
+struct TileQueue<'o, 'a> {
+    state: Mutex<QueueState>,
+    progress: ProgressTracker<'o, 'a>,
+    options: &'o RenderOptions<'a>,
+}
+
+struct QueueState {
+    pending: VecDeque<usize>,
+    remaining: usize,
+    framebuffer: FrameBuffer,
+}
+
+impl TileQueue<'_, '_> {
+    fn next(&self) -> Option<usize> {
+        self.state.lock().unwrap().pending.pop_front()
+    }
+
+    // tiles of a failed worker are rendered first by the others
+    fn give_back(&self, indices: &HashSet<usize>) {
+        let mut state = self.state.lock().unwrap();
+        for index in indices {
+            state.pending.push_front(*index);
+        }
+    }
+
+    fn finish(&self, tile: &Tile, finished: &FinishedTile) {
+        let mut state = self.state.lock().unwrap();
+        state.framebuffer.copy_tile(tile, finished);
+        state.remaining -= 1;
+        self.progress.add(1);
+    }
+
+    // a cancelled render hands out no more tiles
+    fn is_done(&self) -> bool {
+        self.state.lock().unwrap().remaining == 0 || self.options.is_cancelled()
+    }
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    queue: &TileQueue,
+    tiles: &[Tile],
+    job: &Job,
+) -> Result<(), DistributedError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let threads = match receive(&mut reader)? {
+        Reply::Ready { threads } => threads.max(1),
+        _ => return Err(protocol_error("expected the worker to be ready")),
+    };
+    send(&mut writer, &Request::Job(job.clone()))?;
+
+    // enough tiles are in flight to keep all the threads of the worker busy
+    let mut in_flight = HashSet::new();
+    let result = (|| loop {
+        while in_flight.len() < threads && !queue.is_done() {
+            match queue.next() {
+                Some(index) => {
+                    in_flight.insert(index);
+                    send(&mut writer, &Request::Tile { index })?;
+                }
+                None => break,
+            }
+        }
+
+        if in_flight.is_empty() || queue.options.is_cancelled() {
+            if queue.is_done() {
+                return send(&mut writer, &Request::Done);
+            }
+            // the last tiles are being rendered by other workers, which could still fail
+            thread::sleep(Duration::from_millis(20));
+            continue;
+        }
+
+        // the read timeout of the stream, the worker is only read from while it has tiles
+        let reply = match receive(&mut reader) {
+            Err(DistributedError::Io(err))
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                let timeout = writer.read_timeout()?.unwrap_or_default();
+                return Err(DistributedError::TimedOut(timeout));
+            }
+            reply => reply?,
+        };
+        match reply {
+            Reply::Tile(finished) => {
+                let tile = tiles
+                    .get(finished.index)
+                    .filter(|tile| finished.color.len() == tile.pixel_count())
+                    .filter(|_| in_flight.remove(&finished.index))
+                    .ok_or_else(|| protocol_error("unexpected tile"))?;
+                queue.finish(tile, &finished);
+            }
+            Reply::Error { message } => return Err(DistributedError::Protocol(message)),
+            Reply::Ready { .. } => return Err(protocol_error("unexpected ready message")),
+        }
+    })();
+
+    if result.is_err() {
+        queue.give_back(&in_flight);
+    }
+    result
+}
+
+// renders tiles for the coordinator at address, frame after frame, until it goes away
+pub fn run_worker(address: &str) -> Result<(), DistributedError> {
+    let mut scene_file: Option<(String, Vec<Override>, SceneFile)> = None;
+    let mut timeout = FIRST_CONNECTION_TIMEOUT;
+
+    loop {
+        let stream = match connect(address, timeout) {
+            Ok(stream) => stream,
+            // the coordinator is gone once it has rendered all its frames
+            Err(_) if timeout == NEXT_CONNECTION_TIMEOUT => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        serve_coordinator(stream, &mut scene_file)?;
+        timeout = NEXT_CONNECTION_TIMEOUT;
+    }
+}
+
+fn connect(address: &str, timeout: Duration) -> io::Result<TcpStream> {
+    let start = Instant::now();
+    loop {
+        match TcpStream::connect(address) {
+            Ok(stream) => return Ok(stream),
+            Err(err) if start.elapsed() >= timeout => return Err(err),
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+fn serve_coordinator(
+    stream: TcpStream,
+    scene_file: &mut Option<(String, Vec<Override>, SceneFile)>,
+) -> Result<(), DistributedError> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let writer = Mutex::new(stream);
+
+    let ready = Reply::Ready {
+        threads: rayon::current_num_threads(),
+    };
+    let job = match send(&mut *writer.lock().unwrap(), &ready).and_then(|_| receive(&mut reader)) {
+        Ok(Request::Job(job)) => job,
+        // the coordinator went away before handing out a job, it has no frame left to render
+        Ok(Request::Done) | Err(DistributedError::Io(_)) | Err(DistributedError::Disconnected) => {
+            return Ok(())
+        }
+        Ok(Request::Tile { .. }) => return Err(protocol_error("expected a job")),
+        Err(err) => return Err(err),
+    };
+
+    let scene = match load_scene(&job, scene_file) {
+        Ok(scene) => scene,
+        Err(err) => {
+            let message = err.to_string();
+            send(&mut *writer.lock().unwrap(), &Reply::Error { message })?;
+            return Err(err.into());
+        }
+    };
+    let tiles = scene.tile_list();
+    let tile_count = tiles.len();
+    match job.frame {
+        Some(frame) => info!("rendering frame {} of {}", frame, job.scene),
+        None => info!("rendering {}", job.scene),
+    }
+
+    // the requests are read on their own thread while the tiles are rendered in parallel
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|s| {
+        let requests = s.spawn(move || -> Result<(), DistributedError> {
+            loop {
+                match receive(&mut reader)? {
+                    Request::Tile { index } if index < tile_count => {
+                        // the receiver only goes away if sending a tile failed
+                        if sender.send(index).is_err() {
+                            return Ok(());
+                        }
+                    }
+                    Request::Done => return Ok(()),
+                    _ => return Err(protocol_error("expected a tile")),
+                }
+            }
+        });
+
+        receiver.into_iter().par_bridge().try_for_each(|index| {
+            let finished = scene.render_tile(index, &tiles[index]);
+            send(&mut *writer.lock().unwrap(), &Reply::Tile(finished))
+        })?;
+
+        requests.join().unwrap()
+    })
+}
+
+fn load_scene(
+    job: &Job,
+    cache: &mut Option<(String, Vec<Override>, SceneFile)>,
+) -> Result<Scene, SceneError> {
+    // the meshes and backgrounds are only read once for all the frames of a scene
+    let reuse = matches!(cache, Some((scene, overrides, _)) if *scene == job.scene && *overrides == job.overrides);
+    if !reuse {
+        let scene_file = SceneFile::open(&job.scene, &job.overrides)?;
+        *cache = Some((job.scene.clone(), job.overrides.clone(), scene_file));
+    }
+    let (_, _, scene_file) = cache.as_mut().unwrap();
+
+    let mut scene = match job.frame {
+        Some(frame) => scene_file.build_frame(frame)?,
+        None => scene_file.build()?,
+    };
+    scene.camera = job.camera;
+    scene.region = job.region;
+    Ok(scene)
+}
+
+fn send<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<(), DistributedError> {
+    let mut line =
+        serde_json::to_vec(message).map_err(|err| DistributedError::Protocol(err.to_string()))?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    Ok(writer.flush()?)
+}
+
+fn receive<R: BufRead, T: DeserializeOwned>(reader: &mut R) -> Result<T, DistributedError> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Err(DistributedError::Disconnected);
+    }
+    serde_json::from_str(&line).map_err(|err| DistributedError::Protocol(err.to_string()))
+}
+
+fn protocol_error(message: &str) -> DistributedError {
+    DistributedError::Protocol(message.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_distributed_render() {
+        // decoding the background of the scene is slow in debug builds
+        let directory = TestDir::new("distributed");
+        let background = directory.join("background.png");
+        image::RgbImage::from_pixel(2, 2, image::Rgb([20, 40, 80]))
+            .save(&background)
+            .unwrap();
+        let overrides = vec![
+            Override::new("background", json!(background.to_str().unwrap())),
+            Override::new("frame_width", json!(24)),
+            Override::new("frame_height", json!(16)),
+            Override::new("shapes.objs", json!([])),
+            Override::new("tiles.size", json!(4)),
+        ];
+        let scene = Scene::load_with_overrides("scene.json", &overrides).unwrap();
+        let job = Job {
+            scene: String::from("scene.json"),
+            overrides,
+            frame: None,
+            camera: scene.camera,
+            region: None,
+        };
+        let coordinator = Coordinator::bind("127.0.0.1:0")
+            .unwrap()
+            .with_worker_timeout(Duration::from_millis(500));
+        let address = coordinator.local_address().unwrap();
+
+        // connects as a worker and takes a few tiles without rendering them
+        fn take_tiles(address: &str) -> TcpStream {
+            let stream = TcpStream::connect(address).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = stream;
+            send(&mut writer, &Reply::Ready { threads: 3 }).unwrap();
+            let _: Request = receive(&mut reader).unwrap();
+            for _ in 0..3 {
+                let _: Request = receive(&mut reader).unwrap();
+            }
+            writer
+        }
+        // a worker that dies, and one that hangs until the frame is done
+        let (started, failing_workers_ready) = mpsc::channel();
+        let (dying_address, dying_started) = (address.clone(), started.clone());
+        thread::spawn(move || {
+            take_tiles(&dying_address);
+            dying_started.send(()).unwrap();
+        });
+        let (frame_done, hanging) = mpsc::channel::<()>();
+        let hanging_address = address.clone();
+        thread::spawn(move || {
+            let _stream = take_tiles(&hanging_address);
+            started.send(()).unwrap();
+            let _ = hanging.recv();
+        });
+
+        let rendering = thread::spawn(move || {
+            failing_workers_ready.recv().unwrap();
+            failing_workers_ready.recv().unwrap();
+            run_worker(&address)
+        });
+        let framebuffer = coordinator
+            .render(&scene, &job, &RenderOptions::default())
+            .unwrap();
+        drop(frame_done);
+
+        // with a single sample per pixel the render is deterministic
+        assert_eq!(framebuffer.buffer, scene.render().buffer);
+        drop(coordinator);
+        assert!(rendering.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,117 @@
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::scene::SceneError;
+
+// the format of a scene file, from its extension, json for an unknown extension
+// all of them have the same schema, they are read as a json value
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SceneFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl SceneFormat {
+    pub fn from_path(file_path: &str) -> Self {
+        let extension = Path::new(file_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("yaml") | Some("yml") => SceneFormat::Yaml,
+            Some("toml") => SceneFormat::Toml,
+            _ => SceneFormat::Json,
+        }
+    }
+
+    // source is the file the text comes from, for the messages
+    pub fn parse(&self, text: &str, source: &str) -> Result<Value, SceneError> {
+        match self {
+            SceneFormat::Json => {
+                serde_json::from_str(text).map_err(|err| SceneError::Parse(source.to_string(), err))
+            }
+            SceneFormat::Yaml => serde_yaml::from_str(text)
+                .map_err(|err| SceneError::Format(source.to_string(), err.to_string())),
+            SceneFormat::Toml => toml::from_str(text)
+                .map_err(|err| SceneError::Format(source.to_string(), err.to_string())),
+        }
+    }
+
+    pub fn write<T: Serialize>(&self, value: &T, source: &str) -> Result<String, SceneError> {
+        match self {
+            SceneFormat::Json => serde_json::to_string_pretty(value)
+                .map_err(|err| SceneError::Parse(source.to_string(), err)),
+            SceneFormat::Yaml => serde_yaml::to_string(value)
+                .map_err(|err| SceneError::Format(source.to_string(), err.to_string())),
+            SceneFormat::Toml => {
+                // toml has no null, the unset optional fields are left out
+                let mut value = serde_json::to_value(value)
+                    .map_err(|err| SceneError::Parse(source.to_string(), err))?;
+                remove_nulls(&mut value);
+                toml::to_string_pretty(&value)
+                    .map_err(|err| SceneError::Format(source.to_string(), err.to_string()))
+            }
+        }
+    }
+}
+
+fn remove_nulls(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            fields.retain(|_, field| !field.is_null());
+            fields.values_mut().for_each(remove_nulls);
+        }
+        Value::Array(elements) => elements.iter_mut().for_each(remove_nulls),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_from_path() {
+        assert_eq!(SceneFormat::from_path("scene.json"), SceneFormat::Json);
+        assert_eq!(SceneFormat::from_path("scenes/a.YML"), SceneFormat::Yaml);
+        assert_eq!(SceneFormat::from_path("scene.toml"), SceneFormat::Toml);
+        assert_eq!(SceneFormat::from_path("scene"), SceneFormat::Json);
+    }
+
+    #[test]
+    fn test_same_schema() {
+        let yaml = "
+# a comment
+frame_width: 320
+lights:
+  - position: [-20, 20, 20]
+    intensity: 1.5
+";
+        let toml = "
+# a comment
+frame_width = 320
+
+[[lights]]
+position = [-20, 20, 20]
+intensity = 1.5
+";
+        let expected = json!({
+            "frame_width": 320,
+            "lights": [{ "position": [-20, 20, 20], "intensity": 1.5 }]
+        });
+        assert_eq!(SceneFormat::Yaml.parse(yaml, "yaml").unwrap(), expected);
+        assert_eq!(SceneFormat::Toml.parse(toml, "toml").unwrap(), expected);
+
+        let with_null = json!({ "frame_width": 320, "denoise": null, "lights": [] });
+        for format in [SceneFormat::Json, SceneFormat::Yaml, SceneFormat::Toml] {
+            let text = format.write(&with_null, "scene").unwrap();
+            let parsed = format.parse(&text, "scene").unwrap();
+            assert_eq!(parsed["frame_width"], json!(320));
+        }
+    }
+}
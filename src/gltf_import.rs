@@ -0,0 +1,428 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Matrix4, Vector2, Vector3, Vector4};
+use gltf::camera::Projection as GltfProjection;
+use gltf::image::Format;
+use gltf::khr_lights_punctual::Kind;
+use log::warn;
+use serde_json::{json, Value};
+
+use crate::camera::{Camera, Projection};
+use crate::light::Light;
+use crate::scene::SceneError;
+use crate::shapes::material::{Color, Material};
+use crate::shapes::mesh::{Mesh, MeshMaterial};
+use crate::shapes::texture::Texture;
+use crate::shapes::transform::Transform;
+
+// the frame width of the scenes made from a gltf file, the height follows the aspect ratio of its camera
+const FRAME_WIDTH: usize = 640;
+
+// distance at which the directional lights are placed, the lights of the scenes are points
+const DIRECTIONAL_LIGHT_DISTANCE: f32 = 1e4;
+
+pub fn is_gltf(file_path: &str) -> bool {
+    let extension = Path::new(file_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    matches!(extension.as_deref(), Some("gltf") | Some("glb"))
+}
+
+// a scene description for a gltf file: its meshes as a single `gltf` shape,
+// its first camera and its punctual lights (as points, their color is ignored)
+pub fn scene_value(file_path: &str) -> Result<Value, SceneError> {
+    let gltf = gltf::Gltf::open(file_path)
+        .map_err(|err| SceneError::Format(file_path.to_string(), err.to_string()))?;
+
+    let mut camera = None;
+    let mut lights = Vec::new();
+    visit_nodes(&gltf.document, |node, matrix| {
+        let position = (matrix * Vector4::new(0.0, 0.0, 0.0, 1.0)).truncate();
+        // cameras and lights look down their local -z axis
+        let direction = (matrix * Vector4::new(0.0, 0.0, -1.0, 0.0))
+            .truncate()
+            .normalize();
+        if let (None, Some(gltf_camera)) = (&camera, node.camera()) {
+            let up = (matrix * Vector4::new(0.0, 1.0, 0.0, 0.0))
+                .truncate()
+                .normalize();
+            camera = Some((Camera::new(position, direction, up), gltf_camera));
+        }
+        if let Some(light) = node.light() {
+            let position = match light.kind() {
+                Kind::Directional => position - direction * DIRECTIONAL_LIGHT_DISTANCE,
+                Kind::Point | Kind::Spot { .. } => position,
+            };
+            lights.push(Light::new(position, light.intensity()));
+        }
+    });
+
+    let (mut camera, fov_in_degrees, aspect_ratio) = match camera {
+        Some((camera, gltf_camera)) => match gltf_camera.projection() {
+            GltfProjection::Perspective(perspective) => (
+                camera,
+                perspective.yfov().to_degrees(),
+                perspective.aspect_ratio(),
+            ),
+            GltfProjection::Orthographic(orthographic) => (
+                Camera {
+                    projection: Projection::Orthographic {
+                        view_width: 2.0 * orthographic.xmag(),
+                    },
+                    ..camera
+                },
+                60.0,
+                Some(orthographic.xmag() / orthographic.ymag()),
+            ),
+        },
+        None => (Camera::default(), 60.0, None),
+    };
+    camera.focus_distance = Camera::default().focus_distance;
+    let frame_height = (FRAME_WIDTH as f32 / aspect_ratio.unwrap_or(4.0 / 3.0)).round() as usize;
+
+    Ok(json!({
+        "materials": {},
+        "lights": lights,
+        "shapes": [{ "type": "gltf", "file": file_path }],
+        "camera": camera,
+        "frame_width": FRAME_WIDTH,
+        "frame_height": frame_height.max(1),
+        "fov_in_degrees": fov_in_degrees,
+        "max_reflect_depth": 4
+    }))
+}
+
+// all the triangles of the default scene of a gltf file in a single mesh,
+// with their own materials unless a material is given
+pub fn load_mesh(
+    file_path: &str,
+    transform: &Transform,
+    material: Option<Material>,
+) -> Result<Mesh, SceneError> {
+    let (document, buffers, images) = gltf::import(file_path)
+        .map_err(|err| SceneError::Format(file_path.to_string(), err.to_string()))?;
+
+    let mut vertexes = Vec::new();
+    let mut triangles = Vec::new();
+    let mut uvs = Vec::new();
+    let mut triangle_materials = Vec::new();
+    // a replacing material is the only one
+    let mut materials: Vec<MeshMaterial> = material.into_iter().map(MeshMaterial::new).collect();
+    // the index in materials of the gltf materials, and the textures of the gltf images
+    let mut material_indices = HashMap::new();
+    let mut textures = HashMap::new();
+    // the first invalid primitive, the nodes after it are skipped
+    let mut error = None;
+    visit_nodes(&document, |node, matrix| {
+        let mesh = match node.mesh() {
+            Some(mesh) if error.is_none() => mesh,
+            _ => return,
+        };
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                warn!(
+                    "skipping a primitive that isn't made of triangles: [file={}] [mesh={}]",
+                    file_path,
+                    mesh.name().unwrap_or_default()
+                );
+                continue;
+            }
+            let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+            let positions: Vec<Vector3<f32>> = match reader.read_positions() {
+                Some(positions) => positions
+                    .map(|position| {
+                        let position = matrix * Vector3::from(position).extend(1.0);
+                        transform.apply_to_point(position.truncate())
+                    })
+                    .collect(),
+                None => continue,
+            };
+            let indices: Vec<usize> = match reader.read_indices() {
+                Some(indices) => indices.into_u32().map(|index| index as usize).collect(),
+                None => (0..positions.len()).collect(),
+            };
+
+            let gltf_material = primitive.material();
+            let color_texture = gltf_material.pbr_metallic_roughness().base_color_texture();
+            let material_index = match material {
+                Some(_) => 0,
+                None => *material_indices
+                    .entry(gltf_material.index())
+                    .or_insert_with(|| {
+                        let texture = color_texture.as_ref().map(|info| {
+                            let image_index = info.texture().source().index();
+                            textures
+                                .entry(image_index)
+                                .or_insert_with(|| Arc::new(convert_image(&images[image_index])))
+                                .clone()
+                        });
+                        materials.push(MeshMaterial {
+                            material: convert_material(&gltf_material),
+                            texture,
+                        });
+                        materials.len() as u32 - 1
+                    }),
+            };
+            // the texture coordinates, unless the material is replaced
+            let tex_coords: Option<Vec<Vector2<f32>>> = color_texture
+                .filter(|_| material.is_none())
+                .and_then(|info| reader.read_tex_coords(info.tex_coord()))
+                .map(|tex_coords| tex_coords.into_f32().map(Vector2::from).collect());
+            let count = match &tex_coords {
+                Some(tex_coords) => positions.len().min(tex_coords.len()),
+                None => positions.len(),
+            };
+            if let Some(index) = indices.iter().find(|index| **index >= count) {
+                error = Some(SceneError::Format(
+                    file_path.to_string(),
+                    format!(
+                        "vertex index {} out of range in mesh {}, there are {}",
+                        index,
+                        mesh.name().unwrap_or_default(),
+                        count
+                    ),
+                ));
+                return;
+            }
+
+            let offset = vertexes.len();
+            vertexes.extend(positions);
+            for triangle in indices.chunks_exact(3) {
+                // counter clockwise in gltf, the other way around for the mesh
+                let [a, b, c] = [triangle[0], triangle[2], triangle[1]];
+                triangles.push([a, b, c].map(|index| (offset + index) as u32));
+                triangle_materials.push(material_index);
+                uvs.push(match &tex_coords {
+                    Some(tex_coords) => [tex_coords[a], tex_coords[b], tex_coords[c]],
+                    None => [Vector2::new(0.0, 0.0); 3],
+                });
+            }
+        }
+    });
+
+    if let Some(error) = error {
+        return Err(error);
+    }
+    if triangles.is_empty() {
+        return Err(SceneError::Invalid(format!(
+            "{}: no triangles in the gltf file",
+            file_path
+        )));
+    }
+    // only the textures need them
+    if materials.iter().all(|material| material.texture.is_none()) {
+        uvs.clear();
+    }
+    Mesh::new(vertexes, triangles, materials, triangle_materials)?.with_uvs(uvs)
+}
+
+// calls visit with the nodes of the default scene and their transform to the scene
+fn visit_nodes<'a, F: FnMut(gltf::Node<'a>, Matrix4<f32>)>(
+    document: &'a gltf::Document,
+    mut visit: F,
+) {
+    fn visit_node<'a, F: FnMut(gltf::Node<'a>, Matrix4<f32>)>(
+        node: gltf::Node<'a>,
+        parent: Matrix4<f32>,
+        visit: &mut F,
+    ) {
+        let matrix = parent * Matrix4::from(node.transform().matrix());
+        visit(node.clone(), matrix);
+        for child in node.children() {
+            visit_node(child, matrix, visit);
+        }
+    }
+
+    let scene = document
+        .default_scene()
+        .or_else(|| document.scenes().next());
+    if let Some(scene) = scene {
+        for node in scene.nodes() {
+            visit_node(node, Matrix4::from_scale(1.0), &mut visit);
+        }
+    }
+}
+
+// the metallic roughness model turned into weights of the diffuse, specular, reflected and refracted light
+fn convert_material(material: &gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let [red, green, blue, alpha] = pbr.base_color_factor();
+    let metallic = pbr.metallic_factor();
+    let roughness = pbr.roughness_factor();
+    let opacity = match material.alpha_mode() {
+        gltf::material::AlphaMode::Blend => alpha,
+        _ => 1.0,
+    };
+
+    let albedo = Vector4::new(
+        (1.0 - metallic) * opacity,
+        0.5 * (1.0 - roughness) * opacity,
+        metallic * (1.0 - roughness) * opacity,
+        1.0 - opacity,
+    );
+    // a rough surface has a wide highlight
+    let specular_exponent = (2.0 / roughness.powi(4).max(1e-4) - 2.0).clamp(1.0, 1e4);
+    Material::new(albedo, Color::new(red, green, blue), specular_exponent, 1.0)
+}
+
+fn convert_image(image: &gltf::image::Data) -> Texture {
+    let (channels, bytes) = match image.format {
+        Format::R8 => (1, 1),
+        Format::R8G8 => (2, 1),
+        Format::R8G8B8 => (3, 1),
+        Format::R8G8B8A8 => (4, 1),
+        Format::R16 => (1, 2),
+        Format::R16G16 => (2, 2),
+        Format::R16G16B16 => (3, 2),
+        Format::R16G16B16A16 => (4, 2),
+        Format::R32G32B32FLOAT => (3, 4),
+        Format::R32G32B32A32FLOAT => (4, 4),
+    };
+    let value = |data: &[u8]| match bytes {
+        1 => data[0] as f32 / 255.0,
+        2 => u16::from_le_bytes([data[0], data[1]]) as f32 / 65535.0,
+        _ => f32::from_le_bytes([data[0], data[1], data[2], data[3]]),
+    };
+    let pixels = image
+        .pixels
+        .chunks_exact(channels * bytes)
+        .map(|pixel| {
+            let channel = |i: usize| value(&pixel[i.min(channels - 1) * bytes..]);
+            // grey and grey alpha images
+            if channels < 3 {
+                Color::new(channel(0), channel(0), channel(0))
+            } else {
+                Color::new(channel(0), channel(1), channel(2))
+            }
+        })
+        .collect();
+    Texture::new(image.width as usize, image.height as usize, pixels)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use image::{Rgb, RgbImage};
+
+    use crate::overrides::Override;
+    use crate::scene::Scene;
+    use crate::test_dir::TestDir;
+
+    // a textured quad facing the camera, with a point light on the camera
+    fn write_quad(directory: &Path) -> String {
+        std::fs::create_dir_all(directory).unwrap();
+        let mut buffer = Vec::new();
+        for position in [
+            [-2.0f32, -2.0, -5.0],
+            [2.0, -2.0, -5.0],
+            [2.0, 2.0, -5.0],
+            [-2.0, 2.0, -5.0],
+        ] {
+            position
+                .iter()
+                .for_each(|value| buffer.extend(value.to_le_bytes()));
+        }
+        // all on the red pixel of the texture
+        for _ in 0..4 {
+            [0.25f32, 0.5]
+                .iter()
+                .for_each(|value| buffer.extend(value.to_le_bytes()));
+        }
+        for index in [0u16, 1, 2, 0, 2, 3] {
+            buffer.extend(index.to_le_bytes());
+        }
+        std::fs::write(directory.join("quad.bin"), &buffer).unwrap();
+
+        let mut texture = RgbImage::new(2, 1);
+        texture.put_pixel(0, 0, Rgb([255, 0, 0]));
+        texture.put_pixel(1, 0, Rgb([0, 255, 0]));
+        texture.save(directory.join("quad.png")).unwrap();
+
+        let gltf = json!({
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["KHR_lights_punctual"],
+            "extensions": { "KHR_lights_punctual": { "lights": [{ "type": "point", "intensity": 1.5 }] } },
+            "scene": 0,
+            "scenes": [{ "nodes": [0, 1] }],
+            "nodes": [
+                { "mesh": 0, "translation": [0.0, 0.0, -1.0] },
+                { "camera": 0, "children": [2], "translation": [0.0, 0.0, 1.0] },
+                { "extensions": { "KHR_lights_punctual": { "light": 0 } } }
+            ],
+            "cameras": [{ "type": "perspective", "perspective": { "yfov": 1.0, "aspectRatio": 2.0, "znear": 0.1 } }],
+            "meshes": [{ "primitives": [{
+                "attributes": { "POSITION": 0, "TEXCOORD_0": 1 },
+                "indices": 2,
+                "material": 0
+            }] }],
+            "materials": [{ "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": 0 },
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0
+            } }],
+            "textures": [{ "source": 0 }],
+            "images": [{ "uri": "quad.png" }],
+            "buffers": [{ "uri": "quad.bin", "byteLength": buffer.len() }],
+            "bufferViews": [
+                { "buffer": 0, "byteOffset": 0, "byteLength": 48 },
+                { "buffer": 0, "byteOffset": 48, "byteLength": 32 },
+                { "buffer": 0, "byteOffset": 80, "byteLength": 12 }
+            ],
+            "accessors": [
+                { "bufferView": 0, "componentType": 5126, "count": 4, "type": "VEC3",
+                  "min": [-2.0, -2.0, -5.0], "max": [2.0, 2.0, -5.0] },
+                { "bufferView": 1, "componentType": 5126, "count": 4, "type": "VEC2" },
+                { "bufferView": 2, "componentType": 5123, "count": 6, "type": "SCALAR" }
+            ]
+        });
+        let path = directory.join("quad.gltf");
+        std::fs::write(&path, gltf.to_string()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_gltf_scene() {
+        let directory = TestDir::new("gltf");
+        let path = write_quad(directory.path());
+
+        let scene = Scene::load(&path).unwrap();
+        assert_eq!((scene.frame_width, scene.frame_height), (640, 320));
+        assert!((scene.fov + 1.0).abs() < 1e-5);
+        assert_eq!(scene.camera.position, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(scene.lights.len(), 1);
+        assert_eq!(scene.lights[0].position, Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(scene.triangle_count(), 2);
+
+        let overrides = [
+            Override::new("frame_width", json!(8)),
+            Override::new("frame_height", json!(4)),
+        ];
+        let framebuffer = Scene::load_with_overrides(&path, &overrides)
+            .unwrap()
+            .render();
+        // the quad faces the light and takes the red of its texture
+        let center = framebuffer.buffer[2 * 8 + 4];
+        assert!(center.x > 0.5 && center.y == 0.0 && center.z == 0.0);
+    }
+
+    #[test]
+    fn test_gltf_invalid_index() {
+        let directory = TestDir::new("gltf_index");
+        let path = write_quad(directory.path());
+        // the last index points past the four vertexes
+        let bin = directory.join("quad.bin");
+        let mut buffer = std::fs::read(&bin).unwrap();
+        let end = buffer.len();
+        buffer[end - 2..].copy_from_slice(&7u16.to_le_bytes());
+        std::fs::write(&bin, &buffer).unwrap();
+
+        match load_mesh(&path, &Transform::default(), None) {
+            Err(SceneError::Format(_, message)) => assert!(message.contains("index 7")),
+            _ => panic!("expected a format error"),
+        }
+    }
+}
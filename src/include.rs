@@ -0,0 +1,242 @@
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+
+use crate::format::SceneFormat;
+use crate::gltf_import;
+use crate::scene::{self, SceneError};
+
+// reads a scene file and the files it includes with `"include": ["materials/default.json", ...]`
+// the include paths are relative to the including file, the included files can include other files
+// and be in any of the scene formats, or gltf files
+// the included files are merged in order under the including one:
+// materials with the same name are replaced, lights and shapes are added before the ones of the including file
+// and the other fields are replaced
+pub fn read_scene_value(file_path: &str) -> Result<Value, SceneError> {
+    read_with_includes(Path::new(file_path), &mut Vec::new())
+}
+
+// chain is the list of the files being included, to catch a file including itself
+fn read_with_includes(file_path: &Path, chain: &mut Vec<PathBuf>) -> Result<Value, SceneError> {
+    let display = file_path.to_string_lossy().to_string();
+    let canonical = file_path
+        .canonicalize()
+        .map_err(|err| SceneError::Io(display.clone(), err))?;
+    if chain.contains(&canonical) {
+        let cycle: Vec<_> = chain
+            .iter()
+            .skip_while(|path| **path != canonical)
+            .chain(std::iter::once(&canonical))
+            .map(|path| path.to_string_lossy().to_string())
+            .collect();
+        return Err(SceneError::Invalid(format!(
+            "include cycle: {}",
+            cycle.join(" -> ")
+        )));
+    }
+
+    let mut value = if gltf_import::is_gltf(&display) {
+        gltf_import::scene_value(&display)?
+    } else {
+        let text = std::fs::read_to_string(file_path)
+            .map_err(|err| SceneError::Io(display.clone(), err))?;
+        SceneFormat::from_path(&display).parse(&text, &display)?
+    };
+    let fields = match &mut value {
+        Value::Object(fields) => fields,
+        _ => return Ok(value),
+    };
+    let directory = file_path.parent().unwrap_or_else(|| Path::new(""));
+    // the paths of the scene being read are left as they are, the ones of gltf files are already rebased
+    if !chain.is_empty() && !gltf_import::is_gltf(&display) {
+        rebase_paths(fields, directory, &display)?;
+    }
+    let includes = match fields.remove("include") {
+        Some(includes) => serde_json::from_value::<Vec<String>>(includes)
+            .map_err(|err| SceneError::Parse(display.clone(), err))?,
+        None => return Ok(value),
+    };
+
+    chain.push(canonical);
+    let mut merged = Map::new();
+    for include in includes {
+        match read_with_includes(&directory.join(include), chain)? {
+            Value::Object(included) => merge(&mut merged, included, &display)?,
+            _ => {
+                return Err(SceneError::Invalid(format!(
+                    "{}: an included file must be an object",
+                    display
+                )))
+            }
+        }
+    }
+    chain.pop();
+
+    merge(&mut merged, std::mem::take(fields), &display)?;
+    Ok(Value::Object(merged))
+}
+
+// the asset files of an included file are relative to it, they are made relative to the directory
+// of the included file as seen from the including one, like the path of the included file itself
+fn rebase_paths(
+    fields: &mut Map<String, Value>,
+    directory: &Path,
+    source: &str,
+) -> Result<(), SceneError> {
+    let rebase = |path: &mut Value| {
+        if let Value::String(path) = path {
+            *path = directory.join(&*path).to_string_lossy().to_string();
+        }
+    };
+    if let Some(background) = fields.get_mut("background") {
+        rebase(background);
+    }
+    if let Some(shapes) = fields.remove("shapes") {
+        let mut shapes = scene::deserialize_shapes(shapes)
+            .map_err(|err| SceneError::Parse(source.to_string(), err))?;
+        for shape in &mut shapes {
+            let key = match shape.get("type").and_then(Value::as_str) {
+                Some("obj") => "wavefront",
+                Some("gltf") => "file",
+                _ => continue,
+            };
+            if let Some(path) = shape.get_mut(key) {
+                rebase(path);
+            }
+        }
+        fields.insert("shapes".to_string(), Value::Array(shapes));
+    }
+    Ok(())
+}
+
+fn merge(
+    base: &mut Map<String, Value>,
+    top: Map<String, Value>,
+    source: &str,
+) -> Result<(), SceneError> {
+    for (key, value) in top {
+        let merged = match (key.as_str(), base.remove(&key)) {
+            ("materials", Some(Value::Object(mut materials))) => match value {
+                Value::Object(top_materials) => {
+                    materials.extend(top_materials);
+                    Value::Object(materials)
+                }
+                value => value,
+            },
+            ("lights", Some(Value::Array(mut lights))) => match value {
+                Value::Array(top_lights) => {
+                    lights.extend(top_lights);
+                    Value::Array(lights)
+                }
+                value => value,
+            },
+            // the shapes may be in the former layout
+            ("shapes", Some(shapes)) => {
+                let parse = |shapes| {
+                    scene::deserialize_shapes(shapes)
+                        .map_err(|err| SceneError::Parse(source.to_string(), err))
+                };
+                let mut shapes = parse(shapes)?;
+                shapes.extend(parse(value)?);
+                Value::Array(shapes)
+            }
+            (_, _) => value,
+        };
+        base.insert(key, merged);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    use crate::test_dir::TestDir;
+
+    fn write(directory: &Path, name: &str, value: Value) {
+        std::fs::write(directory.join(name), value.to_string()).unwrap();
+    }
+
+    #[test]
+    fn test_include() {
+        let test_dir = TestDir::new("include");
+        let directory = test_dir.path();
+        std::fs::create_dir_all(directory.join("library")).unwrap();
+        write(
+            directory,
+            "library/materials.json",
+            json!({
+                "include": ["lights.json"],
+                "materials": { "ivory": { "specular_exponent": 50.0 }, "mirror": { "specular_exponent": 1425.0 } }
+            }),
+        );
+        write(
+            directory,
+            "library/lights.json",
+            json!({ "lights": [{ "intensity": 1.5 }], "shapes": { "spheres": [{ "radius": 1.0 }] } }),
+        );
+        write(
+            directory,
+            "scene.json",
+            json!({
+                "include": ["library/materials.json"],
+                "materials": { "ivory": { "specular_exponent": 10.0 } },
+                "lights": [{ "intensity": 2.0 }],
+                "shapes": [{ "type": "plane" }],
+                "frame_width": 320
+            }),
+        );
+
+        let value = read_scene_value(directory.join("scene.json").to_str().unwrap()).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "materials": { "ivory": { "specular_exponent": 10.0 }, "mirror": { "specular_exponent": 1425.0 } },
+                "lights": [{ "intensity": 1.5 }, { "intensity": 2.0 }],
+                "shapes": [{ "type": "sphere", "radius": 1.0 }, { "type": "plane" }],
+                "frame_width": 320
+            })
+        );
+
+        // the files used by an included file are next to it
+        write(
+            directory,
+            "library/lights.json",
+            json!({
+                "background": "sky.png",
+                "shapes": {
+                    "objs": [{ "wavefront": "models/duck.obj" }, { "wavefront": "/models/absolute.obj" }]
+                }
+            }),
+        );
+        write(
+            directory,
+            "library/materials.json",
+            json!({ "include": ["lights.json"], "shapes": [{ "type": "gltf", "file": "quad.gltf" }] }),
+        );
+        let value = read_scene_value(directory.join("scene.json").to_str().unwrap()).unwrap();
+        let library = directory.join("library");
+        let path = |name: &str| json!(library.join(name).to_str().unwrap());
+        assert_eq!(value["background"], path("sky.png"));
+        assert_eq!(value["shapes"][0]["wavefront"], path("models/duck.obj"));
+        assert_eq!(
+            value["shapes"][1]["wavefront"],
+            json!("/models/absolute.obj")
+        );
+        assert_eq!(value["shapes"][2]["file"], path("quad.gltf"));
+        assert_eq!(value["shapes"][3], json!({ "type": "plane" }));
+
+        // a file including itself through another one
+        write(
+            directory,
+            "library/lights.json",
+            json!({ "include": ["materials.json"] }),
+        );
+        let cycle = read_scene_value(directory.join("scene.json").to_str().unwrap());
+        assert!(
+            matches!(cycle, Err(SceneError::Invalid(message)) if message.contains("include cycle"))
+        );
+    }
+}
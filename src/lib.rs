@@ -1,4 +1,22 @@
+pub mod animation;
+pub mod camera;
+pub mod denoise;
+pub mod distributed;
+pub mod format;
+pub mod gltf_import;
+pub mod include;
 pub mod light;
+pub mod mesh_cache;
+pub mod mtl;
+pub mod overrides;
+pub mod ply;
+pub mod progressive;
+pub mod render_options;
 pub mod scene;
+pub mod shape_registry;
 pub mod shapes;
+pub mod stl;
+#[cfg(test)]
+mod test_dir;
+pub mod tiles;
 pub mod wavefront;
@@ -1,18 +1,473 @@
-use std::env;
+use std::convert::TryFrom;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+use std::time::Duration;
 
-use image::{ImageResult, RgbImage};
+use clap::{Args, Parser, Subcommand};
+use image::RgbImage;
+use indicatif::ProgressBar;
+use log::{error, info};
 
-use tinygraph_x::scene::{FrameBuffer, Scene};
+use serde_json::json;
+
+use tinygraph_x::animation::{frame_file_name, suffixed_file_name};
+use tinygraph_x::denoise::DenoiseOptions;
+use tinygraph_x::distributed::{self, Coordinator, Job};
+use tinygraph_x::mesh_cache::MeshCache;
+use tinygraph_x::overrides::Override;
+use tinygraph_x::progressive::ProgressiveOptions;
+use tinygraph_x::render_options::RenderOptions;
+use tinygraph_x::scene::{FrameBuffer, Scene, SceneFile};
+use tinygraph_x::tiles::Region;
+
+#[derive(Parser)]
+#[command(
+    version,
+    about = "a tiny raytracer",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    // `tinygraph_x [OUTPUT] [SCENE]` is kept as a shortcut for `tinygraph_x render`
+    #[command(flatten)]
+    render: RenderArgs,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Render a scene to an image
+    Render(Box<RenderArgs>),
+    /// Parse a scene and report errors without rendering it
+    Validate {
+        /// Scene file
+        #[arg(default_value = "scene.json")]
+        scene: String,
+        /// Override a scene parameter before loading the scene, e.g. `--set frame_width=320`
+        #[arg(long = "set", value_name = "PATH=VALUE")]
+        overrides: Vec<Override>,
+    },
+    /// Print statistics about a scene
+    Info {
+        /// Scene file
+        #[arg(default_value = "scene.json")]
+        scene: String,
+        /// Override a scene parameter before loading the scene, e.g. `--set frame_width=320`
+        #[arg(long = "set", value_name = "PATH=VALUE")]
+        overrides: Vec<Override>,
+    },
+    /// Render tiles for a `render --listen` coordinator, from the directory the scene paths are relative to
+    Worker {
+        /// Address of the coordinator, e.g. 127.0.0.1:7878
+        address: String,
+        /// Number of rendering threads (defaults to the number of cpus)
+        #[arg(long)]
+        threads: Option<usize>,
+    },
+    /// Convert a scene file to another scene file
+    Convert {
+        /// Scene file to read
+        input: String,
+        /// Scene file to write, in the format of its extension (.json, .yaml or .toml)
+        output: String,
+        /// Override a scene parameter before writing the scene, e.g. `--set frame_width=320`
+        #[arg(long = "set", value_name = "PATH=VALUE")]
+        overrides: Vec<Override>,
+    },
+}
+
+#[derive(Args)]
+struct RenderArgs {
+    /// Image file to write, `%04d` is replaced by the frame number for animated scenes
+    #[arg(default_value = "out.png")]
+    output: String,
+    /// Scene file
+    #[arg(default_value = "scene.json")]
+    scene: String,
+    /// Override the frame width
+    #[arg(long)]
+    width: Option<usize>,
+    /// Override the frame height
+    #[arg(long)]
+    height: Option<usize>,
+    /// Override the number of samples per pixel
+    #[arg(long)]
+    samples: Option<usize>,
+    /// Override the max reflection/refraction depth
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Number of rendering threads (defaults to the number of cpus)
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Override the size of the square tiles the image is split in
+    #[arg(long)]
+    tile_size: Option<usize>,
+    /// Override the order the tiles are rendered in: scanline, spiral or hilbert
+    #[arg(long)]
+    tile_order: Option<String>,
+    /// Save the finished tiles to OUTPUT.checkpoint, and resume from it if it exists
+    #[arg(long, conflicts_with = "progressive")]
+    checkpoint: bool,
+    /// Only render the window x0,y0,x1,y1 of the image, in pixels or in fractions of the image size (e.g. 0.25,0,0.5,1)
+    #[arg(long, value_name = "X0,Y0,X1,Y1", allow_hyphen_values = true)]
+    region: Option<RegionArg>,
+    /// Paste the rendered region into the existing output image instead of writing it cropped
+    #[arg(long, requires = "region")]
+    composite: bool,
+    /// Hand out the tiles to `tinygraph_x worker` processes connecting to this address instead of rendering them
+    #[arg(long, value_name = "ADDRESS", conflicts_with_all = ["progressive", "checkpoint"])]
+    listen: Option<String>,
+    /// Seconds without a tile from a worker after which its tiles are handed to the other workers
+    #[arg(long, value_name = "SECS", requires = "listen", value_parser = clap::value_parser!(u64).range(1..))]
+    worker_timeout: Option<u64>,
+    /// Run the denoiser after rendering
+    #[arg(long)]
+    denoise: bool,
+    /// Keep the parsed meshes and their bvh in this directory, to load them faster next time
+    #[arg(long, value_name = "DIR")]
+    mesh_cache: Option<String>,
+    /// Only render this frame of an animated scene
+    #[arg(long)]
+    frame: Option<usize>,
+    /// Render passes until stopped, averaging them and writing the image regularly
+    #[arg(long)]
+    progressive: bool,
+    /// Stop the progressive render after this many passes
+    #[arg(long, requires = "progressive")]
+    passes: Option<usize>,
+    /// Stop the progressive render after this many seconds
+    #[arg(long, value_name = "SECS", requires = "progressive", value_parser = parse_seconds)]
+    time_budget: Option<Duration>,
+    /// Stop the progressive render once the estimated relative error goes below this
+    #[arg(long, requires = "progressive")]
+    noise_threshold: Option<f32>,
+    /// Write the image every this many seconds during a progressive render
+    #[arg(long, value_name = "SECS", default_value = "10", value_parser = parse_seconds)]
+    write_interval: Duration,
+    /// Override a scene parameter before loading the scene, e.g. `--set frame_width=320`
+    #[arg(long = "set", value_name = "PATH=VALUE")]
+    overrides: Vec<Override>,
+}
+
+impl RenderArgs {
+    fn progressive_options(&self) -> ProgressiveOptions {
+        ProgressiveOptions {
+            max_passes: self.passes,
+            time_budget: self.time_budget,
+            noise_threshold: self.noise_threshold,
+            update_interval: Some(self.write_interval),
+        }
+    }
+
+    // the flags are shortcuts for the matching scene overrides
+    fn overrides(&self) -> Vec<Override> {
+        let mut overrides = Vec::new();
+        if let Some(width) = self.width {
+            overrides.push(Override::new("frame_width", json!(width)));
+        }
+        if let Some(height) = self.height {
+            overrides.push(Override::new("frame_height", json!(height)));
+        }
+        if let Some(samples) = self.samples {
+            overrides.push(Override::new("samples_per_pixel", json!(samples)));
+        }
+        if let Some(max_depth) = self.max_depth {
+            overrides.push(Override::new("max_reflect_depth", json!(max_depth)));
+        }
+        if let Some(tile_size) = self.tile_size {
+            overrides.push(Override::new("tiles.size", json!(tile_size)));
+        }
+        if let Some(tile_order) = &self.tile_order {
+            overrides.push(Override::new("tiles.order", json!(tile_order)));
+        }
+        overrides.extend(self.overrides.iter().cloned());
+        overrides
+    }
+}
+
+// a number of seconds, that a duration can hold
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    let seconds: f32 = s
+        .trim()
+        .parse()
+        .map_err(|_| format!("not a number: {}", s))?;
+    Duration::try_from_secs_f32(seconds).map_err(|_| format!("not a duration in seconds: {}", s))
+}
+
+// `x0,y0,x1,y1`, in fractions of the image size if any of them has a decimal point
+#[derive(Clone)]
+struct RegionArg {
+    bounds: [f32; 4],
+    normalized: bool,
+}
+
+impl FromStr for RegionArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bounds = s
+            .split(',')
+            .map(|value| value.trim().parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .ok()
+            .and_then(|bounds| <[f32; 4]>::try_from(bounds).ok())
+            .ok_or_else(|| format!("invalid region: {}: expected X0,Y0,X1,Y1", s))?;
+
+        Ok(RegionArg {
+            bounds,
+            normalized: s.contains('.'),
+        })
+    }
+}
+
+impl RegionArg {
+    // the window in image coordinates, the origin being the top left corner
+    fn image_region(&self, width: usize, height: usize) -> Result<Region, String> {
+        let [x0, y0, x1, y1] = self.bounds;
+        let (scale_x, scale_y) = if self.normalized {
+            (width as f32, height as f32)
+        } else {
+            (1.0, 1.0)
+        };
+        let to_pixel = |value: f32, scale: f32, size: usize| {
+            ((value * scale).round().max(0.0) as usize).min(size)
+        };
+        let region = Region {
+            x0: to_pixel(x0, scale_x, width),
+            y0: to_pixel(y0, scale_y, height),
+            x1: to_pixel(x1, scale_x, width),
+            y1: to_pixel(y1, scale_y, height),
+        };
+
+        if region.x0 >= region.x1 || region.y0 >= region.y1 {
+            return Err(format!(
+                "invalid region: {},{},{},{}: empty inside a {}x{} image",
+                x0, y0, x1, y1, width, height
+            ));
+        }
+        Ok(region)
+    }
+}
 
 fn main() {
-    let scene = Scene::from_file(&get_scene_file());
-    let framebuffer = scene.render();
+    // plain messages, RUST_LOG=debug shows more of them
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info"))
+        .format(|buf, record| writeln!(buf, "{}", record.args()))
+        .init();
+
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Some(Command::Render(args)) => render(*args),
+        Some(Command::Validate { scene, overrides }) => validate(&scene, &overrides),
+        Some(Command::Info { scene, overrides }) => info(&scene, &overrides),
+        Some(Command::Convert {
+            input,
+            output,
+            overrides,
+        }) => convert(&input, &output, &overrides),
+        Some(Command::Worker { address, threads }) => worker(&address, threads),
+        None => render(cli.render),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
+
+fn render(args: RenderArgs) -> Result<(), Box<dyn std::error::Error>> {
+    set_thread_count(args.threads)?;
+    let coordinator = match &args.listen {
+        Some(address) => {
+            let mut coordinator = Coordinator::bind(address.as_str())?;
+            if let Some(worker_timeout) = args.worker_timeout {
+                coordinator = coordinator.with_worker_timeout(Duration::from_secs(worker_timeout));
+            }
+            Some(coordinator)
+        }
+        None => None,
+    };
+    let coordinator = coordinator.as_ref();
+
+    let mut scene_file = SceneFile::open(&args.scene, &args.overrides())?;
+    if let Some(mesh_cache) = &args.mesh_cache {
+        scene_file = scene_file.with_mesh_cache(MeshCache::new(mesh_cache));
+    }
+    match scene_file.animation.clone() {
+        Some(animation) => {
+            let frames: Vec<usize> = match args.frame {
+                Some(frame) => vec![frame],
+                None => animation.frames().collect(),
+            };
+            for frame in frames {
+                info!("frame {}:", frame);
+                let scene = scene_file.build_frame(frame as f32)?;
+                let output = frame_file_name(&args.output, frame);
+                render_scene(scene, &args, &output, Some(frame), coordinator)?;
+            }
+        }
+        None => render_scene(scene_file.build()?, &args, &args.output, None, coordinator)?,
+    }
+
+    Ok(())
+}
 
-    export(&framebuffer, &get_out_file()).expect("failed to export to ppm");
+fn set_thread_count(threads: Option<usize>) -> Result<(), rayon::ThreadPoolBuildError> {
+    match threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global(),
+        None => Ok(()),
+    }
+}
+
+fn render_scene(
+    mut scene: Scene,
+    args: &RenderArgs,
+    output: &str,
+    frame: Option<usize>,
+    coordinator: Option<&Coordinator>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // keep the denoiser settings of the scene if it has some
+    if args.denoise && scene.denoise.is_none() {
+        scene.denoise = Some(DenoiseOptions::default());
+    }
+
+    let image_region = match &args.region {
+        Some(region) => Some(region.image_region(scene.frame_width, scene.frame_height)?),
+        None => None,
+    };
+    // the framebuffer is upside down and mirrored compared to the image
+    scene.region = image_region.map(|region| Region {
+        x0: scene.frame_width - region.x1,
+        y0: scene.frame_height - region.y1,
+        x1: scene.frame_width - region.x0,
+        y1: scene.frame_height - region.y0,
+    });
+
+    // one image per eye with a separate stereo camera
+    for (view, camera) in scene.camera.views() {
+        scene.camera = camera;
+        let output = match view {
+            Some(view) => suffixed_file_name(output, view),
+            None => output.to_string(),
+        };
+
+        let progress_bar = ProgressBar::new(0);
+        let mut options = RenderOptions::default().with_progress(|progress| {
+            if let Some(total) = progress.total {
+                progress_bar.set_length(total as u64);
+            }
+            progress_bar.set_position(progress.done as u64);
+        });
+        if args.checkpoint {
+            options = options.with_checkpoint(&checkpoint_file_name(&output));
+        }
+
+        let framebuffer = if args.progressive {
+            // each pass is logged, a bar without a pass budget wouldn't tell much
+            let options = RenderOptions::default();
+            scene.render_progressive(&args.progressive_options(), &options, |framebuffer| {
+                if let Err(err) = export(framebuffer, &output, image_region, args.composite) {
+                    error!("{}", err);
+                }
+            })
+        } else if let Some(coordinator) = coordinator {
+            let job = Job {
+                scene: args.scene.clone(),
+                overrides: args.overrides(),
+                frame: frame.map(|frame| frame as f32),
+                camera: scene.camera,
+                region: scene.region,
+            };
+            coordinator.render(&scene, &job, &options)?
+        } else {
+            scene.render_with_options(&options)?
+        };
+        progress_bar.finish_and_clear();
+
+        export(&framebuffer, &output, image_region, args.composite)?;
+        // the image is complete, the checkpoint isn't needed anymore
+        let checkpoint = checkpoint_file_name(&output);
+        if args.checkpoint && Path::new(&checkpoint).exists() {
+            std::fs::remove_file(checkpoint)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn checkpoint_file_name(output: &str) -> String {
+    format!("{}.checkpoint", output)
+}
+
+fn worker(address: &str, threads: Option<usize>) -> Result<(), Box<dyn std::error::Error>> {
+    set_thread_count(threads)?;
+    distributed::run_worker(address)?;
+    Ok(())
+}
+
+fn validate(scene_file: &str, overrides: &[Override]) -> Result<(), Box<dyn std::error::Error>> {
+    Scene::load_with_overrides(scene_file, overrides)?;
+    println!("{}: ok", scene_file);
+    Ok(())
+}
+
+fn info(scene_file: &str, overrides: &[Override]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = SceneFile::open(scene_file, overrides)?;
+    let scene = file.build()?;
+
+    println!("scene: {}", scene_file);
+    println!("resolution: {}x{}", scene.frame_width, scene.frame_height);
+    println!("materials: {}", scene.materials.len());
+    println!("lights: {}", scene.lights.len());
+    println!("shapes: {}", scene.shapes().len());
+    println!("triangles: {}", scene.triangle_count());
+    match scene.bounding_box() {
+        Some(bounding_box) => println!(
+            "bounds: [{}, {}, {}] -> [{}, {}, {}]",
+            bounding_box.min.x,
+            bounding_box.min.y,
+            bounding_box.min.z,
+            bounding_box.max.x,
+            bounding_box.max.y,
+            bounding_box.max.z
+        ),
+        None => println!("bounds: none"),
+    }
+    if let Some(animation) = &file.animation {
+        println!(
+            "animation: frames {} to {}, {} tracks",
+            animation.frame_start,
+            animation.frame_end,
+            animation.tracks.len()
+        );
+    }
+    Ok(())
 }
 
-fn export(framebuffer: &FrameBuffer, outfile: &str) -> ImageResult<()> {
-    let image_buffer = RgbImage::from_vec(
+fn convert(
+    input: &str,
+    output: &str,
+    overrides: &[Override],
+) -> Result<(), Box<dyn std::error::Error>> {
+    Scene::convert_file(input, output, overrides)?;
+    println!("converted {} to {}", input, output);
+    Ok(())
+}
+
+// writes the image, or only the region of it, cropped or pasted into the existing outfile
+fn export(
+    framebuffer: &FrameBuffer,
+    outfile: &str,
+    region: Option<Region>,
+    composite: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut image_buffer = RgbImage::from_vec(
         framebuffer.width as u32,
         framebuffer.height as u32,
         framebuffer
@@ -30,27 +485,33 @@ fn export(framebuffer: &FrameBuffer, outfile: &str) -> ImageResult<()> {
     )
     .unwrap();
 
-    println!("exporting to {}...", outfile);
+    if let Some(region) = region {
+        let (x, y) = (region.x0 as u32, region.y0 as u32);
+        let (width, height) = (region.width() as u32, region.height() as u32);
+        image_buffer = if composite {
+            let mut existing = image::open(outfile)?.to_rgb8();
+            if existing.dimensions() != image_buffer.dimensions() {
+                return Err(format!(
+                    "can't composite a {}x{} render into {}: it is {}x{}",
+                    framebuffer.width,
+                    framebuffer.height,
+                    outfile,
+                    existing.width(),
+                    existing.height()
+                )
+                .into());
+            }
+            let rendered = image::imageops::crop_imm(&image_buffer, x, y, width, height);
+            image::imageops::replace(&mut existing, &rendered.to_image(), x, y);
+            existing
+        } else {
+            image::imageops::crop_imm(&image_buffer, x, y, width, height).to_image()
+        };
+    }
+
+    info!("exporting to {}...", outfile);
     image_buffer.save(outfile)?;
-    println!("exporting done!");
+    info!("exporting done!");
 
     Ok(())
 }
-
-fn get_scene_file() -> String {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 2 {
-        args[2].clone()
-    } else {
-        String::from("scene.json")
-    }
-}
-
-fn get_out_file() -> String {
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        args[1].clone()
-    } else {
-        String::from("out.png")
-    }
-}
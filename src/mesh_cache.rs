@@ -0,0 +1,188 @@
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Error, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use bincode::Options;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::shapes::bvh::Bvh;
+use crate::shapes::mesh;
+use crate::wavefront::{self, Obj};
+
+// a mesh file parsed and split in triangles, with the hierarchy of the boxes of its faces
+#[derive(Serialize, Deserialize)]
+pub struct MeshFile {
+    pub obj: Obj,
+    pub bvh: Bvh,
+}
+
+impl MeshFile {
+    pub fn new(obj: Obj) -> Self {
+        let bvh = Bvh::build(&mesh::face_boxes(&obj, &obj.vertexes));
+        Self { obj, bvh }
+    }
+
+    // an obj, ply or stl file, from the cache when it has an up to date copy of it
+    pub fn read(file_path: &str, cache: Option<&MeshCache>) -> Result<Self, Error> {
+        match cache {
+            Some(cache) => cache.read(file_path),
+            None => Ok(MeshFile::new(wavefront::read_mesh_file(file_path)?)),
+        }
+    }
+}
+
+// the version of the entries, to change when what they contain changes
+const MAGIC: &[u8; 8] = b"tgxmesh1";
+
+// a directory of parsed mesh files, so the next runs don't have to parse them and build their bvh again
+// an entry is used if the path, the modification time and the hash of its source file haven't changed
+// the mtl files and their textures are always read again
+#[derive(Debug, Clone)]
+pub struct MeshCache {
+    directory: PathBuf,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct CacheKey {
+    path: String,
+    modified: SystemTime,
+    hash: u64,
+}
+
+impl MeshCache {
+    // the directory is created with the first entry
+    pub fn new<P: AsRef<Path>>(directory: P) -> Self {
+        Self {
+            directory: directory.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn read(&self, file_path: &str) -> Result<MeshFile, Error> {
+        let path = fs::canonicalize(file_path)?;
+        let key = CacheKey {
+            path: path.to_string_lossy().to_string(),
+            modified: fs::metadata(&path)?.modified()?,
+            hash: hash(&fs::read(&path)?),
+        };
+        let entry = self
+            .directory
+            .join(format!("{:016x}.mesh", hash(key.path.as_bytes())));
+
+        match read_entry(&entry, &key) {
+            Ok(Some(mut mesh_file)) => {
+                debug!("mesh cache hit: [file={}]", file_path);
+                let directory = path.parent().unwrap_or_else(|| Path::new(""));
+                mesh_file.obj.load_materials(directory)?;
+                return Ok(mesh_file);
+            }
+            Ok(None) => debug!("mesh cache miss: [file={}]", file_path),
+            Err(err) => debug!("invalid mesh cache entry: {}: {}", entry.display(), err),
+        }
+
+        let mesh_file = MeshFile::new(wavefront::read_mesh_file(file_path)?);
+        if let Err(err) = self.write_entry(&entry, &key, &mesh_file) {
+            warn!(
+                "failed to write the mesh cache entry: {}: {}",
+                entry.display(),
+                err
+            );
+        }
+        Ok(mesh_file)
+    }
+
+    // written next to the entry first, so a reader never sees a partial entry
+    fn write_entry(&self, entry: &Path, key: &CacheKey, mesh_file: &MeshFile) -> Result<(), Error> {
+        fs::create_dir_all(&self.directory)?;
+        let partial = entry.with_extension(format!("{}.tmp", std::process::id()));
+        let mut writer = BufWriter::new(File::create(&partial)?);
+        writer.write_all(MAGIC)?;
+        let options = bincode::options();
+        options.serialize_into(&mut writer, key).map_err(invalid)?;
+        options
+            .serialize_into(&mut writer, mesh_file)
+            .map_err(invalid)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&partial, entry)
+    }
+}
+
+// None if there is no entry or if it is stale
+fn read_entry(entry: &Path, key: &CacheKey) -> Result<Option<MeshFile>, Error> {
+    let file = match File::open(entry) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    // a corrupted length can't make it allocate more than the size of the entry
+    let options = bincode::options().with_limit(file.metadata()?.len());
+    let mut reader = BufReader::new(file);
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Ok(None);
+    }
+    let entry_key: CacheKey = options.deserialize_from(&mut reader).map_err(invalid)?;
+    if entry_key != *key {
+        return Ok(None);
+    }
+    Ok(Some(
+        options.deserialize_from(&mut reader).map_err(invalid)?,
+    ))
+}
+
+fn invalid(err: bincode::Error) -> Error {
+    Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+// fnv-1a, stable from one run to the next unlike the hasher of the standard library
+pub(crate) fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::Vector3;
+
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_mesh_cache() {
+        let directory = TestDir::new("mesh_cache");
+        let obj_path = &directory.file("triangle.obj");
+        fs::write(obj_path, "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n").unwrap();
+        let cache = MeshCache::new(directory.join("cache"));
+
+        let mesh_file = cache.read(obj_path).unwrap();
+        assert_eq!(mesh_file.obj.faces, vec![Vector3::new(1, 2, 3)]);
+        let entries = || fs::read_dir(directory.join("cache")).unwrap().count();
+        assert_eq!(entries(), 1);
+
+        // from the entry
+        let cached = cache.read(obj_path).unwrap();
+        assert_eq!(cached.obj.vertexes, mesh_file.obj.vertexes);
+        assert_eq!(cached.bvh, mesh_file.bvh);
+
+        // the source changed, the entry is replaced
+        fs::write(obj_path, "v 0 0 0\nv 2 0 0\nv 0 2 0\nf 1 2 3\n").unwrap();
+        let changed = cache.read(obj_path).unwrap();
+        assert_eq!(changed.obj.vertexes[1], Vector3::new(2.0, 0.0, 0.0));
+        assert_eq!(entries(), 1);
+
+        // a corrupted entry is ignored
+        let entry = fs::read_dir(directory.join("cache"))
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap()
+            .path();
+        fs::write(&entry, b"tgxmesh1 garbage").unwrap();
+        assert_eq!(cache.read(obj_path).unwrap().obj.faces.len(), 1);
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+
+use cgmath::Vector4;
+use serde::{Deserialize, Serialize};
+
+use crate::shapes::material::{Color, Material};
+use crate::shapes::texture::Texture;
+
+// a material of a wavefront mtl file, with the defaults of the format
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MtlMaterial {
+    pub name: String,
+    // Kd, Ks, Ns, Ni, d and illum
+    pub diffuse: Color,
+    pub specular: Color,
+    pub shininess: f32,
+    pub optical_density: f32,
+    pub dissolve: f32,
+    pub illumination: u32,
+    // map_Kd, not saved with the meshes, it is read again with the mtl file
+    #[serde(skip)]
+    pub diffuse_map: Option<Arc<Texture>>,
+}
+
+impl MtlMaterial {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            specular: Color::new(0.0, 0.0, 0.0),
+            shininess: 1.0,
+            optical_density: 1.0,
+            dissolve: 1.0,
+            illumination: 2,
+            diffuse_map: None,
+        }
+    }
+
+    // the specular color becomes the weight of the highlights, and of the reflections for the ray traced illumination models
+    // what isn't opaque is refracted
+    pub fn to_material(&self) -> Material {
+        let opacity = self.dissolve.clamp(0.0, 1.0);
+        let specular = self.specular.x.max(self.specular.y).max(self.specular.z);
+        let highlights = if self.illumination >= 2 {
+            specular
+        } else {
+            0.0
+        };
+        let reflections = if (3..=9).contains(&self.illumination) {
+            specular
+        } else {
+            0.0
+        };
+        Material::new(
+            Vector4::new(
+                opacity,
+                highlights * opacity,
+                reflections * opacity,
+                1.0 - opacity,
+            ),
+            self.diffuse,
+            self.shininess.max(1.0),
+            self.optical_density,
+        )
+    }
+}
+
+fn invalid(line: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid mtl file: {}", line),
+    )
+}
+
+// the texture file names are relative to directory
+pub fn read(buffer: &str, directory: &Path) -> Result<Vec<MtlMaterial>, Error> {
+    let mut materials: Vec<MtlMaterial> = Vec::new();
+    for line in buffer.lines() {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        let (keyword, arguments) = match words.split_first() {
+            Some((keyword, arguments)) if !keyword.starts_with('#') => (*keyword, arguments),
+            _ => continue,
+        };
+        if keyword == "newmtl" {
+            materials.push(MtlMaterial::new(&arguments.join(" ")));
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some(material) => material,
+            None => return Err(invalid(line.trim())),
+        };
+
+        let number = |index: usize| -> Result<f32, Error> {
+            arguments
+                .get(index)
+                .and_then(|argument| argument.parse().ok())
+                .ok_or_else(|| invalid(line.trim()))
+        };
+        // a single value is used for the three components
+        let color = || -> Result<Color, Error> {
+            let red = number(0)?;
+            if arguments.len() < 3 {
+                return Ok(Color::new(red, red, red));
+            }
+            Ok(Color::new(red, number(1)?, number(2)?))
+        };
+        match keyword {
+            "Kd" => material.diffuse = color()?,
+            "Ks" => material.specular = color()?,
+            "Ns" => material.shininess = number(0)?,
+            "Ni" => material.optical_density = number(0)?,
+            "d" => material.dissolve = number(0)?,
+            "Tr" => material.dissolve = 1.0 - number(0)?,
+            "illum" => material.illumination = number(0)? as u32,
+            "map_Kd" => {
+                // the options before the file name are ignored
+                let file_name = arguments.last().ok_or_else(|| invalid(line.trim()))?;
+                material.diffuse_map = Some(Arc::new(read_texture(&directory.join(file_name))?));
+            }
+            // the other statements are ignored
+            _ => {}
+        }
+    }
+    Ok(materials)
+}
+
+fn read_texture(file_path: &Path) -> Result<Texture, Error> {
+    let image = image::open(file_path)
+        .map_err(|err| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("{}: {}", file_path.display(), err),
+            )
+        })?
+        .to_rgb8();
+    let pixels = image
+        .pixels()
+        .map(|pixel| Color::new(pixel[0] as f32, pixel[1] as f32, pixel[2] as f32) / 255.0)
+        .collect();
+    Ok(Texture::new(
+        image.width() as usize,
+        image.height() as usize,
+        pixels,
+    ))
+}
+
+// the position of the material in materials, added with the default values when it is missing
+pub fn material_index(materials: &mut Vec<MtlMaterial>, name: &str) -> usize {
+    match materials.iter().position(|material| material.name == name) {
+        Some(index) => index,
+        None => {
+            materials.push(MtlMaterial::new(name));
+            materials.len() - 1
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let materials = read(
+            "
+# two materials
+newmtl red
+Kd 1 0 0
+Ks 0.5 0.5 0.5
+Ns 50
+illum 2
+
+newmtl glass
+Kd 1.0
+Ks 1 1 1
+Ni 1.5
+d 0.25
+illum 4
+",
+            Path::new("."),
+        )
+        .unwrap();
+
+        assert_eq!(materials.len(), 2);
+        let red = materials[0].to_material();
+        assert_eq!(red.diffuse_color, Color::new(1.0, 0.0, 0.0));
+        assert_eq!(red.albedo, Vector4::new(1.0, 0.5, 0.0, 0.0));
+        assert_eq!(red.specular_exponent, 50.0);
+
+        assert_eq!(materials[1].name, "glass");
+        let glass = materials[1].to_material();
+        assert_eq!(glass.diffuse_color, Color::new(1.0, 1.0, 1.0));
+        assert_eq!(glass.albedo, Vector4::new(0.25, 0.25, 0.25, 0.75));
+        assert_eq!(glass.refractive_index, 1.5);
+
+        // a statement before newmtl
+        assert!(read("Kd 1 1 1", Path::new(".")).is_err());
+    }
+}
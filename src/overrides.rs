@@ -0,0 +1,191 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+// a `path=value` override applied to a scene file before it is deserialized
+// e.g. `frame_width=320`, `materials.ivory.diffuse_color=[1,0,0]`, `lights.0.intensity=2` or `shapes.duck.velocity=[1,0,0]`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Override {
+    pub path: String,
+    pub value: Value,
+}
+
+impl Override {
+    pub fn new(path: &str, value: Value) -> Self {
+        Self {
+            path: path.to_string(),
+            value,
+        }
+    }
+
+    pub fn apply(&self, root: &mut Value) -> Result<(), String> {
+        let mut node = root;
+        for key in self.path.split('.') {
+            node = match node {
+                Value::Object(map) => map
+                    .entry(key)
+                    .or_insert_with(|| Value::Object(Map::new())),
+                Value::Array(array) => {
+                    let len = array.len();
+                    position(array, key)
+                        .and_then(move |index| array.get_mut(index))
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid override: {}: \"{}\" is not an index or a name of an array of {} elements",
+                                self.path, key, len
+                            )
+                        })?
+                }
+                _ => {
+                    return Err(format!(
+                        "invalid override: {}: can't get \"{}\" from a value that is not an object or an array",
+                        self.path, key
+                    ))
+                }
+            };
+        }
+        *node = self.value.clone();
+        Ok(())
+    }
+
+    // the value at the path, None if a key of the path is missing
+    pub fn find<'a>(&self, root: &'a Value) -> Option<&'a Value> {
+        self.path.split('.').try_fold(root, |node, key| match node {
+            Value::Object(map) => map.get(key),
+            Value::Array(array) => position(array, key).and_then(|index| array.get(index)),
+            _ => None,
+        })
+    }
+}
+
+// elements can also be found by their name, e.g. `shapes.duck.velocity`
+fn position(array: &[Value], key: &str) -> Option<usize> {
+    match key.parse::<usize>() {
+        Ok(index) => Some(index),
+        Err(_) => array
+            .iter()
+            .position(|element| element.get("name") == Some(&Value::from(key))),
+    }
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid override: {}: expected PATH=VALUE", s))?;
+        if path.is_empty() {
+            return Err(format!("invalid override: {}: empty path", s));
+        }
+
+        // anything that isn't valid json is taken as a plain string
+        let value =
+            serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+
+        Ok(Override::new(path, value))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "frame_width=320".parse::<Override>(),
+            Ok(Override::new("frame_width", json!(320)))
+        );
+        assert_eq!(
+            "materials.ivory.diffuse_color=[1,0,0]".parse::<Override>(),
+            Ok(Override::new(
+                "materials.ivory.diffuse_color",
+                json!([1, 0, 0])
+            ))
+        );
+        assert_eq!(
+            "background=backgrounds/night.jpg".parse::<Override>(),
+            Ok(Override::new("background", json!("backgrounds/night.jpg")))
+        );
+        assert!("frame_width".parse::<Override>().is_err());
+        assert!("=320".parse::<Override>().is_err());
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut scene = json!({
+            "frame_width": 4096,
+            "materials": { "ivory": { "diffuse_color": { "x": 0.4, "y": 0.4, "z": 0.3 } } },
+            "lights": [ { "intensity": 1.5 }, { "intensity": 1.8 } ],
+            "shapes": [ { "type": "sphere", "radius": 1.0 }, { "type": "sphere", "name": "ball", "radius": 1.0 } ]
+        });
+
+        Override::new("frame_width", json!(320))
+            .apply(&mut scene)
+            .unwrap();
+        Override::new("materials.ivory.diffuse_color", json!([1, 0, 0]))
+            .apply(&mut scene)
+            .unwrap();
+        Override::new("lights.1.intensity", json!(2.0))
+            .apply(&mut scene)
+            .unwrap();
+        Override::new("denoise.iterations", json!(3))
+            .apply(&mut scene)
+            .unwrap();
+        Override::new("shapes.ball.radius", json!(2.0))
+            .apply(&mut scene)
+            .unwrap();
+
+        assert_eq!(
+            scene,
+            json!({
+                "frame_width": 320,
+                "materials": { "ivory": { "diffuse_color": [1, 0, 0] } },
+                "lights": [ { "intensity": 1.5 }, { "intensity": 2.0 } ],
+                "shapes": [ { "type": "sphere", "radius": 1.0 }, { "type": "sphere", "name": "ball", "radius": 2.0 } ],
+                "denoise": { "iterations": 3 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_find() {
+        let scene = json!({
+            "camera": { "aperture_radius": 0.5 },
+            "shapes": [ { "type": "sphere", "name": "ball", "radius": 1.0 } ]
+        });
+
+        assert_eq!(
+            Override::new("camera.aperture_radius", json!(0)).find(&scene),
+            Some(&json!(0.5))
+        );
+        assert_eq!(
+            Override::new("shapes.ball.radius", json!(0)).find(&scene),
+            Some(&json!(1.0))
+        );
+        assert_eq!(
+            Override::new("camera.aperture_radus", json!(0)).find(&scene),
+            None
+        );
+        assert_eq!(Override::new("shapes.1", json!(0)).find(&scene), None);
+    }
+
+    #[test]
+    fn test_apply_invalid_path() {
+        let mut scene = json!({ "frame_width": 4096, "lights": [] });
+
+        assert!(Override::new("lights.0.intensity", json!(2.0))
+            .apply(&mut scene)
+            .is_err());
+        assert!(Override::new("lights.sun.intensity", json!(2.0))
+            .apply(&mut scene)
+            .is_err());
+        assert!(Override::new("frame_width.x", json!(2.0))
+            .apply(&mut scene)
+            .is_err());
+    }
+}
@@ -0,0 +1,422 @@
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+
+use cgmath::Vector3;
+
+use crate::wavefront::Obj;
+
+// a stanford ply parser, ascii or binary
+// the vertices keep their normals (nx, ny, nz) and colors (red, green, blue), the polygons are split in triangles
+pub fn read(data: &[u8]) -> Result<Obj, Error> {
+    let (header, body) = read_header(data)?;
+    let mut body = match header.format {
+        Format::Ascii => Body::Ascii(
+            std::str::from_utf8(body)
+                .map_err(|err| invalid(&err.to_string()))?
+                .split_ascii_whitespace(),
+        ),
+        Format::BinaryLittleEndian => Body::Binary(body, false),
+        Format::BinaryBigEndian => Body::Binary(body, true),
+    };
+
+    let mut obj = Obj::default();
+    for element in &header.elements {
+        match element.name.as_str() {
+            "vertex" => read_vertexes(element, &mut body, &mut obj)?,
+            "face" => read_faces(element, &mut body, &mut obj)?,
+            // skipped
+            _ => {
+                for _ in 0..element.count {
+                    for property in &element.properties {
+                        property.read(&mut body)?;
+                    }
+                }
+            }
+        }
+    }
+
+    if obj.faces.is_empty() {
+        return Err(invalid("no faces"));
+    }
+    if obj
+        .faces
+        .iter()
+        .flat_map(|face| [face.x, face.y, face.z])
+        .any(|index| index > obj.vertexes.len())
+    {
+        return Err(invalid("a face has a vertex that doesn't exist"));
+    }
+    Ok(obj)
+}
+
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Property {
+    name: String,
+    kind: Kind,
+    // the type of the number of items for a list property
+    list: Option<Kind>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Kind {
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Float32,
+    Float64,
+}
+
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    // the data left to read, and whether it is big endian
+    Binary(&'a [u8], bool),
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid ply file: {}", message),
+    )
+}
+
+fn read_header(data: &[u8]) -> Result<(Header, &[u8]), Error> {
+    let mut format = None;
+    let mut elements: Vec<Element> = Vec::new();
+    let mut rest = data;
+    let mut first = true;
+    loop {
+        let end = rest
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or_else(|| invalid("no end_header"))?;
+        let line = String::from_utf8_lossy(&rest[..end]).trim().to_string();
+        rest = &rest[end + 1..];
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+
+        if first {
+            if line != "ply" {
+                return Err(invalid("missing the ply magic number"));
+            }
+            first = false;
+            continue;
+        }
+        match words.as_slice() {
+            ["format", "ascii", _] => format = Some(Format::Ascii),
+            ["format", "binary_little_endian", _] => format = Some(Format::BinaryLittleEndian),
+            ["format", "binary_big_endian", _] => format = Some(Format::BinaryBigEndian),
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| invalid(&line))?,
+                properties: Vec::new(),
+            }),
+            ["property", "list", count_kind, kind, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid(&line))?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    kind: parse_kind(kind)?,
+                    list: Some(parse_kind(count_kind)?),
+                }),
+            ["property", kind, name] => elements
+                .last_mut()
+                .ok_or_else(|| invalid(&line))?
+                .properties
+                .push(Property {
+                    name: name.to_string(),
+                    kind: parse_kind(kind)?,
+                    list: None,
+                }),
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => {}
+            _ => return Err(invalid(&line)),
+        }
+    }
+
+    let format = format.ok_or_else(|| invalid("no format"))?;
+    Ok((Header { format, elements }, rest))
+}
+
+fn parse_kind(kind: &str) -> Result<Kind, Error> {
+    Ok(match kind {
+        "char" | "int8" => Kind::Int8,
+        "uchar" | "uint8" => Kind::UInt8,
+        "short" | "int16" => Kind::Int16,
+        "ushort" | "uint16" => Kind::UInt16,
+        "int" | "int32" => Kind::Int32,
+        "uint" | "uint32" => Kind::UInt32,
+        "float" | "float32" => Kind::Float32,
+        "double" | "float64" => Kind::Float64,
+        _ => return Err(invalid(&format!("unknown property type \"{}\"", kind))),
+    })
+}
+
+impl Body<'_> {
+    fn next(&mut self, kind: Kind) -> Result<f64, Error> {
+        match self {
+            Body::Ascii(words) => words
+                .next()
+                .ok_or_else(|| invalid("unexpected end of file"))?
+                .parse()
+                .map_err(|_| invalid("not a number")),
+            Body::Binary(data, big_endian) => {
+                let size = match kind {
+                    Kind::Int8 | Kind::UInt8 => 1,
+                    Kind::Int16 | Kind::UInt16 => 2,
+                    Kind::Int32 | Kind::UInt32 | Kind::Float32 => 4,
+                    Kind::Float64 => 8,
+                };
+                if data.len() < size {
+                    return Err(invalid("unexpected end of file"));
+                }
+                let mut bytes = data[..size].to_vec();
+                *data = &data[size..];
+                // read as little endian
+                if *big_endian {
+                    bytes.reverse();
+                }
+                Ok(match kind {
+                    Kind::Int8 => bytes[0] as i8 as f64,
+                    Kind::UInt8 => bytes[0] as f64,
+                    Kind::Int16 => i16::from_le_bytes(bytes[..].try_into().unwrap()) as f64,
+                    Kind::UInt16 => u16::from_le_bytes(bytes[..].try_into().unwrap()) as f64,
+                    Kind::Int32 => i32::from_le_bytes(bytes[..].try_into().unwrap()) as f64,
+                    Kind::UInt32 => u32::from_le_bytes(bytes[..].try_into().unwrap()) as f64,
+                    Kind::Float32 => f32::from_le_bytes(bytes[..].try_into().unwrap()) as f64,
+                    Kind::Float64 => f64::from_le_bytes(bytes[..].try_into().unwrap()),
+                })
+            }
+        }
+    }
+}
+
+impl Property {
+    // the values of the property, a single one if it isn't a list
+    fn read(&self, body: &mut Body) -> Result<Vec<f64>, Error> {
+        let count = match self.list {
+            Some(count_kind) => body.next(count_kind)? as usize,
+            None => 1,
+        };
+        (0..count).map(|_| body.next(self.kind)).collect()
+    }
+}
+
+fn read_vertexes(element: &Element, body: &mut Body, obj: &mut Obj) -> Result<(), Error> {
+    let position = |name: &str| {
+        element
+            .properties
+            .iter()
+            .position(|property| property.name == name)
+    };
+    let coordinates = |names: [&str; 3]| -> Option<[usize; 3]> {
+        Some([
+            position(names[0])?,
+            position(names[1])?,
+            position(names[2])?,
+        ])
+    };
+    let vertex =
+        coordinates(["x", "y", "z"]).ok_or_else(|| invalid("vertices without x, y and z"))?;
+    let normal = coordinates(["nx", "ny", "nz"]);
+    let color = coordinates(["red", "green", "blue"]);
+    // integer colors are in [0, 255]
+    let color_scale = match color.map(|color| element.properties[color[0]].kind) {
+        Some(Kind::Float32) | Some(Kind::Float64) => 1.0,
+        Some(Kind::UInt16) => 65535.0,
+        _ => 255.0,
+    };
+
+    for _ in 0..element.count {
+        let mut values = Vec::with_capacity(element.properties.len());
+        for property in &element.properties {
+            // lists are not vertex data, only their first value is kept
+            values.push(property.read(body)?.first().copied().unwrap_or_default());
+        }
+        let vector = |indices: [usize; 3]| {
+            Vector3::new(
+                values[indices[0]] as f32,
+                values[indices[1]] as f32,
+                values[indices[2]] as f32,
+            )
+        };
+        obj.vertexes.push(vector(vertex));
+        if let Some(normal) = normal {
+            obj.normals.push(vector(normal));
+        }
+        if let Some(color) = color {
+            obj.colors.push(vector(color) / color_scale);
+        }
+    }
+    Ok(())
+}
+
+fn read_faces(element: &Element, body: &mut Body, obj: &mut Obj) -> Result<(), Error> {
+    for _ in 0..element.count {
+        for property in &element.properties {
+            let values = property.read(body)?;
+            if property.list.is_none()
+                || !matches!(property.name.as_str(), "vertex_indices" | "vertex_index")
+            {
+                continue;
+            }
+            // whole and positive, the ones past the vertexes are caught once they are all read
+            if let Some(index) = values
+                .iter()
+                .find(|index| !(0.0..u32::MAX as f64).contains(*index) || index.fract() != 0.0)
+            {
+                return Err(invalid(&format!("{} is not a vertex index", index)));
+            }
+            // a fan of triangles, indexed from 1 like obj faces
+            let indices: Vec<usize> = values.iter().map(|index| *index as usize + 1).collect();
+            for i in 1..indices.len().saturating_sub(1) {
+                obj.faces
+                    .push(Vector3::new(indices[0], indices[i], indices[i + 1]));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::shapes::material::Color;
+
+    #[test]
+    fn test_read_ascii() {
+        let obj = read(
+            b"ply
+format ascii 1.0
+comment a square
+element vertex 4
+property float x
+property float y
+property float z
+property float nx
+property float ny
+property float nz
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+0 0 0 0 0 1 255 0 0
+1 0 0 0 0 1 0 255 0
+1 1 0 0 0 1 0 0 255
+0 1 0 0 0 1 255 255 255
+4 0 1 2 3
+",
+        )
+        .unwrap();
+
+        assert_eq!(obj.vertexes.len(), 4);
+        assert_eq!(obj.vertexes[2], Vector3::new(1.0, 1.0, 0.0));
+        assert_eq!(
+            obj.faces,
+            vec![Vector3::new(1, 2, 3), Vector3::new(1, 3, 4)]
+        );
+        assert_eq!(obj.normals[3], Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(obj.colors[1], Color::new(0.0, 1.0, 0.0));
+
+        let with_face = |face: &str| {
+            read(
+                format!(
+                    "ply
+format ascii 1.0
+element vertex 3
+property float x
+property float y
+property float z
+element face 1
+property list uchar double vertex_indices
+end_header
+0 0 0
+1 0 0
+1 1 0
+{}
+",
+                    face
+                )
+                .as_bytes(),
+            )
+        };
+        assert!(with_face("3 0 1 2").is_ok());
+        for face in [
+            "3 0 1 -1",
+            "3 0 1 1e30",
+            "3 0 1 1.5",
+            "3 0 1 nan",
+            "3 0 1 3",
+        ] {
+            assert_eq!(
+                with_face(face).err().map(|err| err.kind()),
+                Some(ErrorKind::InvalidData),
+                "{}",
+                face
+            );
+        }
+    }
+
+    #[test]
+    fn test_read_binary() {
+        let mut data = b"ply
+format binary_big_endian 1.0
+element vertex 3
+property double x
+property double y
+property double z
+element material 1
+property uchar index
+element face 1
+property uchar flags
+property list uchar uint vertex_indices
+end_header
+"
+        .to_vec();
+        for value in [0.0f64, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0] {
+            data.extend(value.to_be_bytes());
+        }
+        data.push(7);
+        data.push(0);
+        data.push(3);
+        for index in [0u32, 1, 2] {
+            data.extend(index.to_be_bytes());
+        }
+
+        let obj = read(&data).unwrap();
+
+        assert_eq!(obj.vertexes[1], Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(obj.faces, vec![Vector3::new(1, 2, 3)]);
+        assert!(obj.normals.is_empty() && obj.colors.is_empty());
+
+        // cut short
+        assert!(read(&data[..data.len() - 2]).is_err());
+
+        // points only
+        let err = read(b"ply\nformat ascii 1.0\nelement vertex 1\nproperty float x\nproperty float y\nproperty float z\nend_header\n0 0 0\n")
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,174 @@
+use std::time::Duration;
+
+use num::Zero;
+use serde::{Deserialize, Serialize};
+
+use crate::scene::{FrameBuffer, Pixel};
+
+// when to stop a progressive render, it runs until it's stopped if none is set
+#[derive(Debug, Clone, Default)]
+pub struct ProgressiveOptions {
+    pub max_passes: Option<usize>,
+    pub time_budget: Option<Duration>,
+    // stop once the estimated relative error of the image goes below this
+    pub noise_threshold: Option<f32>,
+    // how often the current image is handed back while rendering
+    pub update_interval: Option<Duration>,
+}
+
+// extra samples only go to the pixels whose estimated relative error is above tolerance
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveSampling {
+    pub min_samples: usize,
+    pub max_samples: usize,
+    pub tolerance: f32,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        Self {
+            min_samples: 4,
+            max_samples: 64,
+            tolerance: 0.02,
+        }
+    }
+}
+
+// running per pixel sums of the samples of a render
+pub struct Accumulator {
+    pub width: usize,
+    pub height: usize,
+    pub passes: usize,
+    samples: Vec<u32>,
+    color_sum: Vec<Pixel>,
+    color_squared_sum: Vec<Pixel>,
+    albedo_sum: Vec<Pixel>,
+    normal_sum: Vec<Pixel>,
+}
+
+impl Accumulator {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            passes: 0,
+            samples: vec![0; width * height],
+            color_sum: vec![Pixel::zero(); width * height],
+            color_squared_sum: vec![Pixel::zero(); width * height],
+            albedo_sum: vec![Pixel::zero(); width * height],
+            normal_sum: vec![Pixel::zero(); width * height],
+        }
+    }
+
+    // every pixel of the pass counts as one sample
+    pub fn add_pass(&mut self, pass: &FrameBuffer) {
+        for (index, color) in pass.buffer.iter().enumerate() {
+            self.add_sample(index, *color, pass.albedo[index], pass.normal[index]);
+        }
+        self.passes += 1;
+    }
+
+    pub fn add_sample(&mut self, index: usize, color: Pixel, albedo: Pixel, normal: Pixel) {
+        self.samples[index] += 1;
+        self.color_sum[index] += color;
+        self.color_squared_sum[index] += color.zip(color, |a, b| a * b);
+        self.albedo_sum[index] += albedo;
+        self.normal_sum[index] += normal;
+    }
+
+    pub fn sample_count(&self, index: usize) -> usize {
+        self.samples[index] as usize
+    }
+
+    // the average of all the samples so far
+    pub fn framebuffer(&self) -> FrameBuffer {
+        let average = |sums: &[Pixel]| {
+            sums.iter()
+                .zip(self.samples.iter())
+                .map(|(sum, samples)| sum / (*samples).max(1) as f32)
+                .collect()
+        };
+        FrameBuffer {
+            width: self.width,
+            height: self.height,
+            buffer: average(&self.color_sum),
+            albedo: average(&self.albedo_sum),
+            normal: average(&self.normal_sum),
+        }
+    }
+
+    // standard error of the mean of a pixel, relative to its brightness
+    pub fn pixel_error(&self, index: usize) -> f32 {
+        let samples = self.samples[index] as f32;
+        if samples < 2.0 {
+            return f32::INFINITY;
+        }
+        let mean = luminance(self.color_sum[index] / samples);
+        let squared_mean = luminance(self.color_squared_sum[index] / samples);
+        let variance = (squared_mean - mean * mean).max(0.0) * samples / (samples - 1.0);
+
+        (variance / samples).sqrt() / (mean + 1e-3)
+    }
+
+    // average relative error of the image
+    pub fn noise(&self) -> f32 {
+        (0..self.width * self.height)
+            .map(|index| self.pixel_error(index))
+            .sum::<f32>()
+            / (self.width * self.height) as f32
+    }
+}
+
+// perceived brightness of a color
+pub fn luminance(color: Pixel) -> f32 {
+    0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn make_pass(color: Pixel) -> FrameBuffer {
+        FrameBuffer {
+            width: 2,
+            height: 1,
+            buffer: vec![color, Pixel::new(0.5, 0.5, 0.5)],
+            albedo: vec![Pixel::zero(); 2],
+            normal: vec![Pixel::zero(); 2],
+        }
+    }
+
+    #[test]
+    fn test_accumulate() {
+        let mut accumulator = Accumulator::new(2, 1);
+        accumulator.add_pass(&make_pass(Pixel::new(0.2, 0.2, 0.2)));
+        assert_eq!(accumulator.pixel_error(1), f32::INFINITY);
+
+        accumulator.add_pass(&make_pass(Pixel::new(0.6, 0.6, 0.6)));
+
+        let framebuffer = accumulator.framebuffer();
+        assert!((framebuffer.buffer[0] - Pixel::new(0.4, 0.4, 0.4)).x.abs() < 1e-6);
+        assert!((framebuffer.buffer[1] - Pixel::new(0.5, 0.5, 0.5)).x.abs() < 1e-6);
+
+        // the pixel that changed between passes is noisy, the other one isn't
+        assert!(accumulator.pixel_error(0) > 0.1);
+        assert!(accumulator.pixel_error(1) < 1e-3);
+    }
+
+    #[test]
+    fn test_per_pixel_samples() {
+        let mut accumulator = Accumulator::new(2, 1);
+        let zero = Pixel::zero();
+        accumulator.add_sample(0, Pixel::new(1.0, 1.0, 1.0), zero, zero);
+        accumulator.add_sample(0, Pixel::new(0.0, 0.0, 0.0), zero, zero);
+        accumulator.add_sample(1, Pixel::new(0.5, 0.5, 0.5), zero, zero);
+
+        assert_eq!(accumulator.sample_count(0), 2);
+        assert_eq!(accumulator.sample_count(1), 1);
+
+        let framebuffer = accumulator.framebuffer();
+        assert!((framebuffer.buffer[0].x - 0.5).abs() < 1e-6);
+        assert!((framebuffer.buffer[1].x - 0.5).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,157 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// how far along a render is, in tiles, pixels or passes depending on how the scene is rendered
+#[derive(Debug, Copy, Clone)]
+pub struct Progress {
+    pub done: usize,
+    // unknown for progressive renders without a pass budget
+    pub total: Option<usize>,
+    pub elapsed: Duration,
+    pub eta: Option<Duration>,
+}
+
+// cheap to clone, cancelling one of the clones cancels them all
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+type ProgressCallback<'a> = Box<dyn Fn(&Progress) + Send + Sync + 'a>;
+
+// how a render is driven by its caller, the messages go through the `log` facade
+#[derive(Default)]
+pub struct RenderOptions<'a> {
+    pub on_progress: Option<ProgressCallback<'a>>,
+    // checked between tiles, a cancelled render returns SceneError::Cancelled
+    // except for progressive renders which return the passes done so far
+    pub cancellation: Option<CancellationToken>,
+    // the finished tiles are saved to this file and the ones already in it are not rendered again
+    pub checkpoint: Option<String>,
+}
+
+impl<'a> RenderOptions<'a> {
+    pub fn with_progress<F: Fn(&Progress) + Send + Sync + 'a>(mut self, on_progress: F) -> Self {
+        self.on_progress = Some(Box::new(on_progress));
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancellation: CancellationToken) -> Self {
+        self.cancellation = Some(cancellation);
+        self
+    }
+
+    pub fn with_checkpoint(mut self, checkpoint: &str) -> Self {
+        self.checkpoint = Some(checkpoint.to_string());
+        self
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(|cancellation| cancellation.is_cancelled())
+    }
+
+    pub(crate) fn progress_tracker(
+        &self,
+        done: usize,
+        total: Option<usize>,
+    ) -> ProgressTracker<'_, 'a> {
+        ProgressTracker {
+            options: self,
+            start: Instant::now(),
+            initial: done,
+            done: AtomicUsize::new(done),
+            total,
+        }
+    }
+}
+
+// counts the work done from several threads and reports it to the progress callback
+pub(crate) struct ProgressTracker<'o, 'a> {
+    options: &'o RenderOptions<'a>,
+    start: Instant,
+    // work done before the tracker started (resumed tiles) doesn't count for the eta
+    initial: usize,
+    done: AtomicUsize,
+    total: Option<usize>,
+}
+
+impl ProgressTracker<'_, '_> {
+    pub fn add(&self, count: usize) {
+        let done = self.done.fetch_add(count, Ordering::Relaxed) + count;
+        self.report(done);
+    }
+
+    pub fn set(&self, done: usize) {
+        self.done.store(done, Ordering::Relaxed);
+        self.report(done);
+    }
+
+    fn report(&self, done: usize) {
+        if let Some(on_progress) = &self.options.on_progress {
+            let elapsed = self.start.elapsed();
+            let eta = self.total.filter(|_| done > self.initial).map(|total| {
+                elapsed.mul_f64(total.saturating_sub(done) as f64 / (done - self.initial) as f64)
+            });
+            on_progress(&Progress {
+                done,
+                total: self.total,
+                elapsed,
+                eta,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_progress() {
+        let reports = Mutex::new(Vec::new());
+        let options = RenderOptions::default()
+            .with_progress(|progress| reports.lock().unwrap().push(*progress));
+
+        {
+            let tracker = options.progress_tracker(2, Some(10));
+            tracker.add(1);
+            tracker.add(7);
+        }
+        drop(options);
+
+        let reports = reports.into_inner().unwrap();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].done, 3);
+        assert_eq!(reports[1].done, 10);
+        assert_eq!(reports[1].eta, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let cancellation = CancellationToken::new();
+        let options = RenderOptions::default().with_cancellation(cancellation.clone());
+
+        assert!(!options.is_cancelled());
+        cancellation.cancel();
+        assert!(options.is_cancelled());
+    }
+}
@@ -1,36 +1,66 @@
 use std::collections::HashMap;
 use std::f32::consts::PI;
-use std::fs::File;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::Instant;
 
 use cgmath::{InnerSpace, Vector3};
 use image::io::Reader as ImageReader;
 use image::{Rgb, RgbImage};
-use indicatif::ParallelProgressIterator;
+use log::{debug, info};
 use num::Zero;
+use rand::Rng;
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 
+use crate::animation::Animation;
+use crate::camera::{Camera, Convergence, Projection};
+use crate::denoise::DenoiseOptions;
+use crate::format::SceneFormat;
+use crate::gltf_import;
+use crate::include;
 use crate::light::Light;
+use crate::mesh_cache::{self, MeshCache, MeshFile};
+use crate::overrides::Override;
+use crate::progressive::{Accumulator, AdaptiveSampling, ProgressiveOptions};
+use crate::render_options::RenderOptions;
+use crate::shape_registry::{parse_shape, ShapeContext, ShapeRegistry};
 use crate::shapes::material::Material;
+use crate::tiles::{self, Checkpoint, CheckpointHeader, FinishedTile, Region, Tile, TileOptions};
 
+use crate::shapes::bounding_box::BoundingBox;
 use crate::shapes::checkboard_disk::CheckBoardDisk;
 use crate::shapes::disk::Disk;
 use crate::shapes::mesh::Mesh;
+use crate::shapes::moving::{Moving, Shutter};
 use crate::shapes::plane::Plane;
 use crate::shapes::polygon::Polygon;
 use crate::shapes::shape::{Ray, RayHit, Shape};
 use crate::shapes::sphere::Sphere;
+use crate::shapes::transform::Transform;
 
 pub struct Scene {
     pub materials: HashMap<String, Material>,
     pub lights: Vec<Light>,
-    pub shapes: Vec<Box<dyn Shape + Sync>>,
+    // changed through add_shape and remove_shape, which keep the description in sync
+    shapes: Vec<Box<dyn Shape + Sync>>,
     pub background: RgbImage,
+    pub camera: Camera,
 
     pub frame_width: usize,
     pub frame_height: usize,
     pub fov: f32,
     pub max_reflect_depth: usize,
+    pub samples_per_pixel: usize,
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    pub denoise: Option<DenoiseOptions>,
+    pub shutter: Shutter,
+    pub tiles: TileOptions,
+    // only this part of the frame is traced, the rest is left black
+    pub region: Option<Region>,
+
+    // what the scene was built from, the shapes can't be turned back into their description
+    description: SceneJson,
 }
 
 pub type Pixel = Vector3<f32>;
@@ -39,136 +69,564 @@ pub struct FrameBuffer {
     pub width: usize,
     pub height: usize,
     pub buffer: Vec<Pixel>,
+    // first hit guide buffers, used by the denoiser
+    pub albedo: Vec<Pixel>,
+    pub normal: Vec<Pixel>,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(String, std::io::Error),
+    Parse(String, serde_json::Error),
+    // a yaml or toml file that can't be read or written
+    Format(String, String),
+    Image(String, image::ImageError),
+    UnknownMaterial(String),
+    Override(String),
+    Invalid(String),
+    Cancelled,
+}
+
+impl FrameBuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            buffer: vec![Pixel::zero(); width * height],
+            albedo: vec![Pixel::zero(); width * height],
+            normal: vec![Pixel::zero(); width * height],
+        }
+    }
+
+    pub fn copy_tile(&mut self, tile: &Tile, finished: &FinishedTile) {
+        for (i, index) in tile.pixel_indices(self.width).enumerate() {
+            self.buffer[index] = finished.color[i];
+            self.albedo[index] = finished.albedo[i];
+            self.normal[index] = finished.normal[i];
+        }
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SceneError::Io(file, err) => write!(f, "failed to open file: {}: \"{}\"", file, err),
+            SceneError::Parse(file, err) => write!(f, "failed to parse file: {}: {}", file, err),
+            SceneError::Format(file, err) => write!(f, "failed to parse file: {}: {}", file, err),
+            SceneError::Image(file, err) => write!(f, "failed to decode image: {}: {}", file, err),
+            SceneError::UnknownMaterial(name) => write!(f, "unknown material: \"{}\"", name),
+            SceneError::Override(err) => write!(f, "{}", err),
+            SceneError::Invalid(err) => write!(f, "invalid scene: {}", err),
+            SceneError::Cancelled => write!(f, "render cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+// what a single primary ray brings back
+#[derive(Copy, Clone)]
+struct PixelSample {
+    color: Pixel,
+    albedo: Pixel,
+    normal: Pixel,
 }
 
 impl Scene {
     pub fn from_file(file_path: &str) -> Self {
-        println!("importing scene: [file={}]", file_path);
-        let scene_json = SceneJson::from_file(file_path);
-        let shapes = scene_json
-            .shapes
-            .spheres
-            .iter()
-            .map(|sphere| {
-                Box::new(sphere.clone().into_sphere(&scene_json.materials)) as Box<dyn Shape + Sync>
-            })
-            .chain(scene_json.shapes.planes.iter().map(|plane| {
-                Box::new(plane.clone().into_plane(&scene_json.materials)) as Box<dyn Shape + Sync>
-            }))
-            .chain(scene_json.shapes.disks.iter().map(|disk| {
-                Box::new(disk.clone().into_disk(&scene_json.materials)) as Box<dyn Shape + Sync>
-            }))
-            .chain(scene_json.shapes.checkboard_disks.iter().map(|disk| {
-                Box::new(disk.clone().into_checkboard_disk(&scene_json.materials))
-                    as Box<dyn Shape + Sync>
-            }))
-            .chain(scene_json.shapes.polygons.iter().map(|polygon| {
-                Box::new(polygon.clone().into_polygon(&scene_json.materials))
-                    as Box<dyn Shape + Sync>
-            }))
-            .chain(scene_json.shapes.objs.iter().map(|obj| {
-                Box::new(obj.clone().into_mesh(&scene_json.materials)) as Box<dyn Shape + Sync>
-            }))
-            .collect();
-        println!("importing scene done!");
+        Scene::load(file_path).unwrap_or_else(|err| panic!("{}", err))
+    }
 
-        println!("importing background: [file={}]", scene_json.background);
-        let background = Scene::create_background(&scene_json.background);
-        println!("importing background done!");
+    pub fn load(file_path: &str) -> Result<Self, SceneError> {
+        Scene::load_with_overrides(file_path, &[])
+    }
 
-        Self {
-            materials: scene_json.materials,
-            lights: scene_json.lights,
-            shapes,
-            background,
-            frame_width: scene_json.frame_width,
-            frame_height: scene_json.frame_height,
-            fov: -scene_json.fov_in_degrees * (PI / 180.0),
-            max_reflect_depth: scene_json.max_reflect_depth,
+    pub fn load_with_overrides(
+        file_path: &str,
+        overrides: &[Override],
+    ) -> Result<Self, SceneError> {
+        SceneFile::open(file_path, overrides)?.build()
+    }
+
+    // re-writes a scene file, filling in the defaults of the optional fields
+    // the format of the output is taken from its extension, e.g. a json scene converted to yaml
+    pub fn convert_file(
+        input_path: &str,
+        output_path: &str,
+        overrides: &[Override],
+    ) -> Result<(), SceneError> {
+        let scene_json = SceneFile::open(input_path, overrides)?.scene_json(&[])?;
+        let text = SceneFormat::from_path(output_path).write(&scene_json, output_path)?;
+        std::fs::write(output_path, text)
+            .map_err(|err| SceneError::Io(output_path.to_string(), err))
+    }
+
+    // the scene in the format of the scene files, with the lights and settings as they are now
+    // and the shapes as they were described, e.g. meshes are still references to their obj files
+    // the shapes keep a copy of their material, changing the materials only affects the saved scene
+    pub fn to_json(&self) -> Result<String, SceneError> {
+        SceneFormat::Json.write(&self.scene_json()?, "<scene>")
+    }
+
+    // in the format of the extension of the file
+    pub fn save(&self, file_path: &str) -> Result<(), SceneError> {
+        let text = SceneFormat::from_path(file_path).write(&self.scene_json()?, file_path)?;
+        std::fs::write(file_path, text).map_err(|err| SceneError::Io(file_path.to_string(), err))
+    }
+
+    fn scene_json(&self) -> Result<SceneJson, SceneError> {
+        let added = self
+            .description
+            .shapes
+            .iter()
+            .filter(|shape| shape.is_null())
+            .count();
+        if added > 0 {
+            return Err(SceneError::Invalid(format!(
+                "{} shapes were added in code, they can't be saved",
+                added
+            )));
         }
+
+        let description = &self.description;
+        // converting the fov back and forth could change its last digits
+        let fov_in_degrees = if -description.fov_in_degrees * (PI / 180.0) == self.fov {
+            description.fov_in_degrees
+        } else {
+            -self.fov * 180.0 / PI
+        };
+        Ok(SceneJson {
+            materials: self.materials.clone(),
+            lights: self.lights.clone(),
+            camera: self.camera,
+            frame_width: self.frame_width,
+            frame_height: self.frame_height,
+            fov_in_degrees,
+            max_reflect_depth: self.max_reflect_depth,
+            samples_per_pixel: self.samples_per_pixel,
+            adaptive_sampling: self.adaptive_sampling,
+            denoise: self.denoise,
+            shutter: self.shutter,
+            tiles: self.tiles,
+            ..description.clone()
+        })
     }
 
-    fn create_background(background_file: &str) -> RgbImage {
-        ImageReader::open(background_file)
-            .unwrap_or_else(|err| {
-                panic!(
-                    "failed to open background file: {}: {}",
-                    background_file, err
-                )
-            })
+    fn create_background(background_file: &str) -> Result<RgbImage, SceneError> {
+        Ok(ImageReader::open(background_file)
+            .map_err(|err| SceneError::Io(background_file.to_string(), err))?
             .decode()
-            .unwrap_or_else(|err| {
-                panic!(
-                    "failed to decode background file: {}: {}",
-                    background_file, err
-                )
-            })
-            .to_rgb8()
+            .map_err(|err| SceneError::Image(background_file.to_string(), err))?
+            .to_rgb8())
+    }
+
+    pub fn shapes(&self) -> &[Box<dyn Shape + Sync>] {
+        &self.shapes
+    }
+
+    // a shape added in code has no description, the scene can't be saved anymore
+    pub fn add_shape(&mut self, shape: Box<dyn Shape + Sync>) {
+        self.shapes.push(shape);
+        self.description.shapes.push(serde_json::Value::Null);
+    }
+
+    pub fn remove_shape(&mut self, index: usize) -> Box<dyn Shape + Sync> {
+        self.description.shapes.remove(index);
+        self.shapes.remove(index)
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.shapes.iter().map(|shape| shape.triangle_count()).sum()
+    }
+
+    // returns the box enclosing all the bounded shapes of the scene
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.shapes
+            .iter()
+            .filter_map(|shape| shape.bounding_box())
+            .reduce(|acc, bounding_box| acc.union(&bounding_box))
     }
 
     pub fn render(&self) -> FrameBuffer {
-        println!("rendering...");
-        let framebuffer = FrameBuffer {
+        self.render_with_options(&RenderOptions::default())
+            .expect("a render without checkpoint nor cancellation can't fail")
+    }
+
+    pub fn render_with_options(&self, options: &RenderOptions) -> Result<FrameBuffer, SceneError> {
+        // the passes of adaptive sampling go over the whole frame, there are no finished tiles to save
+        if self.adaptive_sampling.is_some() && options.checkpoint.is_some() {
+            return Err(SceneError::Invalid(
+                "checkpoints can't be used with adaptive sampling".to_string(),
+            ));
+        }
+        info!("rendering...");
+        let mut framebuffer = match &self.adaptive_sampling {
+            Some(adaptive_sampling) => self
+                .render_adaptive(adaptive_sampling, options)?
+                .framebuffer(),
+            None => match &options.checkpoint {
+                Some(checkpoint_path) => self.render_with_checkpoint(checkpoint_path, options)?,
+                None => self.render_tiles(Vec::new(), None, options)?,
+            },
+        };
+        info!("rendering done!");
+
+        self.run_denoiser(&mut framebuffer);
+
+        Ok(framebuffer)
+    }
+
+    // the scene value as it was resolved, with the includes and the overrides
+    // its objects are sorted by key, so the same scene always gives the same hash
+    fn description_hash(&self) -> u64 {
+        let value = serde_json::to_value(&self.description)
+            .expect("a scene description can always be converted to a json value");
+        mesh_cache::hash(value.to_string().as_bytes())
+    }
+
+    // the finished tiles are saved to a checkpoint file and the tiles already in it are not rendered again
+    fn render_with_checkpoint(
+        &self,
+        checkpoint_path: &str,
+        options: &RenderOptions,
+    ) -> Result<FrameBuffer, SceneError> {
+        let header = CheckpointHeader {
             width: self.frame_width,
             height: self.frame_height,
-            buffer: (0..self.frame_height)
-                .into_par_iter()
-                .progress()
-                .map(|y| self.render_line(y))
-                .flatten()
-                .collect(),
+            samples_per_pixel: self.samples_per_pixel,
+            tiles: self.tiles,
+            region: self.render_region(),
+            scene_hash: self.description_hash(),
         };
-        println!("rendering done!");
+        let tiles = self.tile_list();
+        let (checkpoint, finished) = Checkpoint::open(checkpoint_path, &header, &tiles)
+            .map_err(|err| SceneError::Io(checkpoint_path.to_string(), err))?;
+        if !finished.is_empty() {
+            info!("resuming, {}/{} tiles done", finished.len(), tiles.len());
+        }
+
+        self.render_tiles(
+            finished,
+            Some((checkpoint_path, &Mutex::new(checkpoint))),
+            options,
+        )
+    }
+
+    // renders passes until one of the stop conditions is met or the render is cancelled,
+    // averaging them together, on_update is called with the current image every progressive.update_interval
+    pub fn render_progressive<F: FnMut(&FrameBuffer)>(
+        &self,
+        progressive: &ProgressiveOptions,
+        options: &RenderOptions,
+        mut on_update: F,
+    ) -> FrameBuffer {
+        info!("rendering progressively...");
+        let start = Instant::now();
+        let mut last_update = Instant::now();
+        let mut accumulator = Accumulator::new(self.frame_width, self.frame_height);
+        let progress = options.progress_tracker(0, progressive.max_passes);
+
+        loop {
+            accumulator.add_pass(&self.render_pass(true));
+            let noise = accumulator.noise();
+            progress.add(1);
+            info!(
+                "pass {}: noise {:.4}, {:.1}s",
+                accumulator.passes,
+                noise,
+                start.elapsed().as_secs_f32()
+            );
+
+            let done = progressive
+                .max_passes
+                .is_some_and(|max_passes| accumulator.passes >= max_passes)
+                || progressive
+                    .time_budget
+                    .is_some_and(|time_budget| start.elapsed() >= time_budget)
+                || progressive
+                    .noise_threshold
+                    .is_some_and(|noise_threshold| noise <= noise_threshold)
+                || options.is_cancelled();
+            if done {
+                break;
+            }
+
+            if let Some(update_interval) = progressive.update_interval {
+                if last_update.elapsed() >= update_interval {
+                    let mut framebuffer = accumulator.framebuffer();
+                    self.run_denoiser(&mut framebuffer);
+                    on_update(&framebuffer);
+                    last_update = Instant::now();
+                }
+            }
+        }
+        info!("rendering done!");
+
+        let mut framebuffer = accumulator.framebuffer();
+        self.run_denoiser(&mut framebuffer);
 
         framebuffer
     }
 
-    fn render_line(&self, y: usize) -> Vec<Pixel> {
-        (0..self.frame_width)
-            .map(|x| {
-                let ray_dir_x = (2.0 * (x as f32 + 0.5) / self.frame_width as f32 - 1.0)
-                    * (self.fov / 2.0).tan()
-                    * self.frame_width as f32
-                    / self.frame_height as f32;
+    // renders min_samples samples at a time for the pixels that are still too noisy
+    fn render_adaptive(
+        &self,
+        adaptive_sampling: &AdaptiveSampling,
+        options: &RenderOptions,
+    ) -> Result<Accumulator, SceneError> {
+        let mut accumulator = Accumulator::new(self.frame_width, self.frame_height);
+        let batch_size = adaptive_sampling.min_samples.max(2);
+        let region = self.render_region();
+        let mut noisy_pixels: Vec<usize> = (0..self.frame_width * self.frame_height)
+            .filter(|index| region.contains(index % self.frame_width, index / self.frame_width))
+            .collect();
+        let pixel_count = noisy_pixels.len();
+        let progress = options.progress_tracker(0, Some(pixel_count));
+
+        while !noisy_pixels.is_empty() {
+            if options.is_cancelled() {
+                return Err(SceneError::Cancelled);
+            }
+            debug!("sampling {} pixels...", noisy_pixels.len());
+            let samples: Vec<(usize, Vec<PixelSample>)> = noisy_pixels
+                .par_iter()
+                .map_init(rand::thread_rng, |rng, &index| {
+                    let (x, y) = (index % self.frame_width, index / self.frame_width);
+                    // the last batch only goes up to max_samples
+                    let remaining = adaptive_sampling.max_samples - accumulator.sample_count(index);
+                    let samples = (0..batch_size.min(remaining))
+                        .map(|_| self.render_pixel_sample(x, y, true, rng))
+                        .collect();
+                    (index, samples)
+                })
+                .collect();
+
+            for (index, pixel_samples) in samples {
+                for sample in pixel_samples {
+                    accumulator.add_sample(index, sample.color, sample.albedo, sample.normal);
+                }
+            }
+
+            noisy_pixels.retain(|&index| {
+                accumulator.sample_count(index) < adaptive_sampling.max_samples
+                    && accumulator.pixel_error(index) > adaptive_sampling.tolerance
+            });
+            progress.set(pixel_count - noisy_pixels.len());
+        }
+
+        Ok(accumulator)
+    }
+
+    // renders the tiles that are not finished yet, in the order of the tile options
+    fn render_tiles(
+        &self,
+        finished: Vec<FinishedTile>,
+        checkpoint: Option<(&str, &Mutex<Checkpoint>)>,
+        options: &RenderOptions,
+    ) -> Result<FrameBuffer, SceneError> {
+        let tiles = self.tile_list();
+        let framebuffer = Mutex::new(FrameBuffer::new(self.frame_width, self.frame_height));
+
+        let mut done = vec![false; tiles.len()];
+        for tile in finished {
+            done[tile.index] = true;
+            framebuffer
+                .lock()
+                .unwrap()
+                .copy_tile(&tiles[tile.index], &tile);
+        }
+
+        let remaining: Vec<usize> = (0..tiles.len()).filter(|index| !done[*index]).collect();
+        let progress = options.progress_tracker(tiles.len() - remaining.len(), Some(tiles.len()));
+        // par_bridge hands out the tiles in order, unlike par_iter which splits the list
+        remaining.iter().par_bridge().try_for_each(|&index| {
+            if options.is_cancelled() {
+                return Err(SceneError::Cancelled);
+            }
+            let tile = self.render_tile(index, &tiles[index]);
+            framebuffer.lock().unwrap().copy_tile(&tiles[index], &tile);
+            if let Some((checkpoint_path, checkpoint)) = checkpoint {
+                checkpoint
+                    .lock()
+                    .unwrap()
+                    .add(&tile)
+                    .map_err(|err| SceneError::Io(checkpoint_path.to_string(), err))?;
+            }
+            progress.add(1);
+            Ok(())
+        })?;
+
+        Ok(framebuffer.into_inner().unwrap())
+    }
+
+    // the tiles of the region to render, in rendering order
+    pub fn tile_list(&self) -> Vec<Tile> {
+        tiles::split_region(&self.render_region(), &self.tiles)
+    }
+
+    pub fn render_tile(&self, index: usize, tile: &Tile) -> FinishedTile {
+        let mut rng = rand::thread_rng();
+        let samples: Vec<PixelSample> = (tile.y..tile.y + tile.height)
+            .flat_map(|y| (tile.x..tile.x + tile.width).map(move |x| (x, y)))
+            .map(|(x, y)| self.render_pixel(x, y, self.samples_per_pixel > 1, &mut rng))
+            .collect();
+
+        FinishedTile {
+            index,
+            color: samples.iter().map(|sample| sample.color).collect(),
+            albedo: samples.iter().map(|sample| sample.albedo).collect(),
+            normal: samples.iter().map(|sample| sample.normal).collect(),
+        }
+    }
 
-                let ray_dir_y = -(2.0 * (y as f32 + 0.5) / self.frame_height as f32 - 1.0)
-                    * (self.fov / 2.0).tan();
+    pub fn run_denoiser(&self, framebuffer: &mut FrameBuffer) {
+        if let Some(denoise) = &self.denoise {
+            info!("denoising...");
+            framebuffer.buffer = denoise.denoise(framebuffer);
+            info!("denoising done!");
+        }
+    }
 
-                let ray_dir = Vector3::new(ray_dir_x, ray_dir_y, -1.0).normalize();
-                let ray = Ray::new(Vector3::zero(), ray_dir);
+    // renders samples_per_pixel samples for every pixel
+    fn render_pass(&self, jitter: bool) -> FrameBuffer {
+        let samples: Vec<PixelSample> = (0..self.frame_height)
+            .into_par_iter()
+            .flat_map(|y| self.render_line(y, jitter))
+            .collect();
+
+        FrameBuffer {
+            width: self.frame_width,
+            height: self.frame_height,
+            buffer: samples.iter().map(|sample| sample.color).collect(),
+            albedo: samples.iter().map(|sample| sample.albedo).collect(),
+            normal: samples.iter().map(|sample| sample.normal).collect(),
+        }
+    }
 
-                self.cast_ray(&ray, 0)
+    fn render_line(&self, y: usize, jitter: bool) -> Vec<PixelSample> {
+        let mut rng = rand::thread_rng();
+        let region = self.render_region();
+        (0..self.frame_width)
+            .map(|x| {
+                if region.contains(x, y) {
+                    self.render_pixel(x, y, jitter, &mut rng)
+                } else {
+                    PixelSample {
+                        color: Pixel::zero(),
+                        albedo: Pixel::zero(),
+                        normal: Pixel::zero(),
+                    }
+                }
             })
             .collect()
     }
 
+    fn render_region(&self) -> Region {
+        self.region
+            .unwrap_or_else(|| Region::full(self.frame_width, self.frame_height))
+    }
+
+    // average of samples_per_pixel samples
+    fn render_pixel<R: Rng>(&self, x: usize, y: usize, jitter: bool, rng: &mut R) -> PixelSample {
+        let samples_per_pixel = self.samples_per_pixel.max(1);
+        let sum = (0..samples_per_pixel)
+            .map(|_| self.render_pixel_sample(x, y, jitter, rng))
+            .fold(
+                PixelSample {
+                    color: Pixel::zero(),
+                    albedo: Pixel::zero(),
+                    normal: Pixel::zero(),
+                },
+                |acc, sample| PixelSample {
+                    color: acc.color + sample.color,
+                    albedo: acc.albedo + sample.albedo,
+                    normal: acc.normal + sample.normal,
+                },
+            );
+
+        PixelSample {
+            color: sum.color / samples_per_pixel as f32,
+            albedo: sum.albedo / samples_per_pixel as f32,
+            normal: sum.normal / samples_per_pixel as f32,
+        }
+    }
+
+    fn render_pixel_sample<R: Rng>(
+        &self,
+        x: usize,
+        y: usize,
+        jitter: bool,
+        rng: &mut R,
+    ) -> PixelSample {
+        let (offset_x, offset_y) = if jitter {
+            (rng.gen::<f32>(), rng.gen::<f32>())
+        } else {
+            (0.5, 0.5)
+        };
+        let time = self.shutter.open + rng.gen::<f32>() * (self.shutter.close - self.shutter.open);
+        self.render_sample(x as f32 + offset_x, y as f32 + offset_y, time, rng)
+    }
+
+    fn render_sample<R: Rng>(&self, x: f32, y: f32, time: f32, rng: &mut R) -> PixelSample {
+        let ray = match self.camera.primary_ray(
+            x,
+            y,
+            (self.frame_width, self.frame_height),
+            self.fov,
+            time,
+            rng,
+        ) {
+            Some(ray) => ray,
+            None => {
+                return PixelSample {
+                    color: Pixel::zero(),
+                    albedo: Pixel::zero(),
+                    normal: Pixel::zero(),
+                }
+            }
+        };
+
+        match self.scene_intersect(&ray) {
+            Some(ray_hit) => PixelSample {
+                color: self.shade(&ray, &ray_hit, 0),
+                albedo: ray_hit.material.diffuse_color,
+                normal: ray_hit.hit_normal.normalize(),
+            },
+            None => {
+                let background = self.get_background_pixel(ray.direction);
+                PixelSample {
+                    color: background,
+                    albedo: background,
+                    normal: Pixel::zero(),
+                }
+            }
+        }
+    }
+
     fn cast_ray(&self, ray: &Ray, depth: usize) -> Pixel {
         if depth > self.max_reflect_depth {
             return self.get_background_pixel(ray.direction);
         }
 
         match self.scene_intersect(ray) {
-            Some(ray_hit) => {
-                let reflect_color = self.calc_reflect(ray, &ray_hit, depth);
-
-                let refract_color = self.calc_refract(ray, &ray_hit, depth);
-
-                let (diffuse_light_intensity, specular_light_intensity) =
-                    self.calc_lights(ray, &ray_hit);
-
-                ray_hit.material.diffuse_color
-                    * diffuse_light_intensity
-                    * ray_hit.material.albedo[0]
-                    + Vector3::new(1.0, 1.0, 1.0)
-                        * specular_light_intensity
-                        * ray_hit.material.albedo[1]
-                    + reflect_color * ray_hit.material.albedo[2]
-                    + refract_color * ray_hit.material.albedo[3]
-            }
+            Some(ray_hit) => self.shade(ray, &ray_hit, depth),
             None => self.get_background_pixel(ray.direction),
         }
     }
 
+    fn shade(&self, ray: &Ray, ray_hit: &RayHit, depth: usize) -> Pixel {
+        let reflect_color = self.calc_reflect(ray, ray_hit, depth);
+
+        let refract_color = self.calc_refract(ray, ray_hit, depth);
+
+        let (diffuse_light_intensity, specular_light_intensity) = self.calc_lights(ray, ray_hit);
+
+        ray_hit.material.diffuse_color * diffuse_light_intensity * ray_hit.material.albedo[0]
+            + Vector3::new(1.0, 1.0, 1.0) * specular_light_intensity * ray_hit.material.albedo[1]
+            + reflect_color * ray_hit.material.albedo[2]
+            + refract_color * ray_hit.material.albedo[3]
+    }
+
     fn scene_intersect(&self, ray: &Ray) -> Option<RayHit> {
         // get the shape with the shortest distance to orig
         self.shapes
@@ -216,7 +674,7 @@ impl Scene {
                 } else {
                     ray_hit.hit_point + ray_hit.hit_normal * 1e-3
                 };
-                let shadow_ray = Ray::new(shadow_orig, light_dir);
+                let shadow_ray = Ray::new_at_time(shadow_orig, light_dir, ray.time);
 
                 if let Some(shadow_hit) = self.scene_intersect(&shadow_ray) {
                     if (shadow_hit.hit_point - shadow_orig).magnitude() < light_distance {
@@ -244,7 +702,7 @@ impl Scene {
         } else {
             ray_hit.hit_point + ray_hit.hit_normal * 1e-3
         };
-        let reflect_ray = Ray::new(reflect_orig, reflect_dir);
+        let reflect_ray = Ray::new_at_time(reflect_orig, reflect_dir, ray.time);
         self.cast_ray(&reflect_ray, depth + 1)
     }
 
@@ -259,7 +717,7 @@ impl Scene {
         } else {
             ray_hit.hit_point + ray_hit.hit_normal * 1e-3
         };
-        let refract_ray = Ray::new(refract_orig, refract_dir);
+        let refract_ray = Ray::new_at_time(refract_orig, refract_dir, ray.time);
         self.cast_ray(&refract_ray, depth + 1)
     }
 }
@@ -288,26 +746,115 @@ fn refract(incoming: Vector3<f32>, normal: Vector3<f32>, refractive_index: f32)
     }
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct SceneJson {
+    #[serde(serialize_with = "serialize_sorted")]
     pub materials: HashMap<String, MaterialJson>,
     pub lights: Vec<LightJson>,
-    pub shapes: ShapesJson,
-    pub background: String,
+    #[serde(default, deserialize_with = "deserialize_shapes")]
+    pub shapes: Vec<ShapeJson>,
+    // a black background if none is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background: Option<String>,
+    #[serde(default)]
+    pub camera: Camera,
     pub frame_width: usize,
     pub frame_height: usize,
     pub fov_in_degrees: f32,
     pub max_reflect_depth: usize,
+    #[serde(default = "default_samples_per_pixel")]
+    pub samples_per_pixel: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive_sampling: Option<AdaptiveSampling>,
+    #[serde(default)]
+    pub denoise: Option<DenoiseOptions>,
+    #[serde(default)]
+    pub shutter: Shutter,
+    #[serde(default)]
+    pub tiles: TileOptions,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub animation: Option<Animation>,
+}
+
+// written in a stable order
+fn serialize_sorted<S: serde::Serializer, V: Serialize>(
+    map: &HashMap<String, V>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter()
+        .collect::<std::collections::BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+fn default_samples_per_pixel() -> usize {
+    1
+}
+
+// a `{"type": "sphere", "name": "...", ...}` entry, built through the shape registry
+// the name is optional, it lets overrides and animations find the shape, e.g. `shapes.duck.velocity`
+type ShapeJson = serde_json::Value;
+
+// the shape categories of the former layout, `"shapes": {"spheres": [...], "planes": [...], ...}`
+const SHAPE_CATEGORIES: [(&str, &str); 6] = [
+    ("spheres", "sphere"),
+    ("planes", "plane"),
+    ("disks", "disk"),
+    ("checkboard_disks", "checkboard_disk"),
+    ("polygons", "polygon"),
+    ("objs", "obj"),
+];
+
+// takes a list of tagged shapes, or the former layout with one list per category
+pub(crate) fn deserialize_shapes<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<ShapeJson>, D::Error> {
+    use serde::de::Error;
+
+    let mut categories = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Array(shapes) => return Ok(shapes),
+        serde_json::Value::Object(categories) => categories,
+        other => return Err(D::Error::custom(format!("invalid shapes: {}", other))),
+    };
+    let mut shapes = Vec::new();
+    for (category, tag) in SHAPE_CATEGORIES.iter() {
+        let entries = match categories.remove(*category) {
+            Some(serde_json::Value::Array(entries)) => entries,
+            Some(other) => {
+                return Err(D::Error::custom(format!(
+                    "invalid shapes: \"{}\" is not a list: {}",
+                    category, other
+                )))
+            }
+            None => continue,
+        };
+        for mut entry in entries {
+            match &mut entry {
+                serde_json::Value::Object(fields) => {
+                    fields.insert(String::from("type"), serde_json::json!(tag));
+                }
+                other => {
+                    return Err(D::Error::custom(format!(
+                        "invalid shapes: an entry of \"{}\" is not an object: {}",
+                        category, other
+                    )))
+                }
+            }
+            shapes.push(entry);
+        }
+    }
+    if let Some(category) = categories.keys().next() {
+        return Err(D::Error::custom(format!(
+            "invalid shapes: unknown category \"{}\"",
+            category
+        )));
+    }
+    Ok(shapes)
 }
 
-#[derive(Serialize, Deserialize)]
-struct ShapesJson {
-    pub spheres: Vec<SphereJson>,
-    pub planes: Vec<PlaneJson>,
-    pub disks: Vec<DiskJson>,
-    pub checkboard_disks: Vec<CheckBoardDiskJson>,
-    pub polygons: Vec<PolygonJson>,
-    pub objs: Vec<ObjJson>,
+fn tagged<T: Serialize>(tag: &str, shape: T) -> ShapeJson {
+    let mut value = serde_json::to_value(shape).unwrap();
+    value["type"] = serde_json::json!(tag);
+    value
 }
 
 type MaterialJson = Material;
@@ -319,6 +866,8 @@ struct SphereJson {
     center: Vector3<f32>,
     radius: f32,
     material: String,
+    #[serde(default = "Vector3::zero", skip_serializing_if = "Vector3::is_zero")]
+    velocity: Vector3<f32>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -326,6 +875,8 @@ struct PlaneJson {
     point: Vector3<f32>,
     normal: Vector3<f32>,
     material: String,
+    #[serde(default = "Vector3::zero", skip_serializing_if = "Vector3::is_zero")]
+    velocity: Vector3<f32>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -334,6 +885,8 @@ struct DiskJson {
     normal: Vector3<f32>,
     radius: f32,
     material: String,
+    #[serde(default = "Vector3::zero", skip_serializing_if = "Vector3::is_zero")]
+    velocity: Vector3<f32>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -344,6 +897,8 @@ struct CheckBoardDiskJson {
     dist_between_mats: f32,
     material1: String,
     material2: String,
+    #[serde(default = "Vector3::zero", skip_serializing_if = "Vector3::is_zero")]
+    velocity: Vector3<f32>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -352,73 +907,879 @@ struct PolygonJson {
     vertex_1: Vector3<f32>,
     vertex_2: Vector3<f32>,
     material: String,
+    #[serde(default = "Vector3::zero", skip_serializing_if = "Vector3::is_zero")]
+    velocity: Vector3<f32>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
+// the mesh file can be an obj, ply or stl file
 struct ObjJson {
     wavefront: String,
-    material: String,
+    // the materials of the mtl files are used when none is given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<String>,
+    #[serde(default, skip_serializing_if = "Transform::is_identity")]
+    transform: Transform,
+    #[serde(default = "Vector3::zero", skip_serializing_if = "Vector3::is_zero")]
+    velocity: Vector3<f32>,
+}
+
+// the meshes of a gltf file, with their own materials unless one is given
+#[derive(Serialize, Deserialize, Clone)]
+struct GltfJson {
+    file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    material: Option<String>,
+    #[serde(default, skip_serializing_if = "Transform::is_identity")]
+    transform: Transform,
+}
+
+// a scene file that has been read but not built yet
+// the parsed meshes and backgrounds are kept around so animated scenes can be built once per frame
+pub struct SceneFile {
+    file_path: String,
+    scene_value: serde_json::Value,
+    overrides: Vec<Override>,
+    pub animation: Option<Animation>,
+
+    objs: HashMap<String, MeshFile>,
+    backgrounds: HashMap<String, RgbImage>,
+    shape_registry: ShapeRegistry,
+    mesh_cache: Option<MeshCache>,
+}
+
+impl SceneFile {
+    pub fn open(file_path: &str, overrides: &[Override]) -> Result<Self, SceneError> {
+        let scene_value = include::read_scene_value(file_path)?;
+
+        let mut scene_file = Self {
+            file_path: file_path.to_string(),
+            scene_value,
+            overrides: overrides.to_vec(),
+            animation: None,
+            objs: HashMap::new(),
+            backgrounds: HashMap::new(),
+            shape_registry: ShapeRegistry::default(),
+            mesh_cache: None,
+        };
+        scene_file.animation = scene_file.scene_json(&[])?.animation;
+
+        Ok(scene_file)
+    }
+
+    // for scene files using shapes defined outside of this crate
+    pub fn with_shape_registry(mut self, shape_registry: ShapeRegistry) -> Self {
+        self.shape_registry = shape_registry;
+        self
+    }
+
+    // the mesh files are read from the cache when it has them, and saved to it otherwise
+    pub fn with_mesh_cache(mut self, mesh_cache: MeshCache) -> Self {
+        self.mesh_cache = Some(mesh_cache);
+        self
+    }
+
+    pub fn build(&mut self) -> Result<Scene, SceneError> {
+        let scene_json = self.scene_json(&[])?;
+        build_scene(
+            scene_json,
+            &self.file_path,
+            &self.shape_registry,
+            &mut self.objs,
+            self.mesh_cache.as_ref(),
+            &mut self.backgrounds,
+        )
+    }
+
+    // builds the scene with its animated parameters set to their value at frame
+    pub fn build_frame(&mut self, frame: f32) -> Result<Scene, SceneError> {
+        let animation_overrides = match &self.animation {
+            Some(animation) => animation.overrides_at(frame),
+            None => Vec::new(),
+        };
+        let scene_json = self.scene_json(&animation_overrides)?;
+        build_scene(
+            scene_json,
+            &self.file_path,
+            &self.shape_registry,
+            &mut self.objs,
+            self.mesh_cache.as_ref(),
+            &mut self.backgrounds,
+        )
+    }
+
+    // the overrides given when opening the file win over the animated values
+    fn scene_json(&self, animation_overrides: &[Override]) -> Result<SceneJson, SceneError> {
+        let mut scene_value = self.scene_value.clone();
+        for scene_override in animation_overrides.iter().chain(self.overrides.iter()) {
+            scene_override
+                .apply(&mut scene_value)
+                .map_err(SceneError::Override)?;
+        }
+        let scene_json: SceneJson = serde_json::from_value(scene_value)
+            .map_err(|err| SceneError::Parse(self.file_path.clone(), err))?;
+
+        // the unknown fields are dropped when deserializing, an override of a misspelled one would do nothing,
+        // the shapes are kept as they are written so only the paths outside of them are checked
+        let known_fields = serde_json::to_value(&scene_json)
+            .map_err(|err| SceneError::Parse(self.file_path.clone(), err))?;
+        for scene_override in animation_overrides.iter().chain(self.overrides.iter()) {
+            let in_shapes = scene_override.path.split('.').next() == Some("shapes");
+            if !in_shapes
+                && !scene_override.value.is_null()
+                && scene_override.find(&known_fields).is_none()
+            {
+                return Err(SceneError::Override(format!(
+                    "invalid override: {}: not a field of the scene",
+                    scene_override.path
+                )));
+            }
+        }
+        Ok(scene_json)
+    }
+}
+
+// source is the file the scene comes from, for the messages
+fn build_scene(
+    scene_json: SceneJson,
+    source: &str,
+    shape_registry: &ShapeRegistry,
+    objs: &mut HashMap<String, MeshFile>,
+    mesh_cache: Option<&MeshCache>,
+    backgrounds: &mut HashMap<String, RgbImage>,
+) -> Result<Scene, SceneError> {
+    scene_json.validate()?;
+    info!("importing scene: [file={}]", source);
+    let shutter = &scene_json.shutter;
+    let mut context = ShapeContext {
+        materials: &scene_json.materials,
+        objs,
+        mesh_cache,
+    };
+    let mut shapes = Vec::with_capacity(scene_json.shapes.len());
+    for value in &scene_json.shapes {
+        let velocity = match value.get("velocity") {
+            Some(velocity) => serde_json::from_value(velocity.clone())
+                .map_err(|err| SceneError::Parse(source.to_string(), err))?,
+            None => Vector3::zero(),
+        };
+        let shape = shape_registry.deserialize(value.clone(), &mut context)?;
+        shapes.push(into_moving(shape, velocity, shutter));
+    }
+    info!("importing scene done!");
+
+    let background = match &scene_json.background {
+        Some(background_file) => match backgrounds.get(background_file) {
+            Some(background) => background.clone(),
+            None => {
+                info!("importing background: [file={}]", background_file);
+                let background = Scene::create_background(background_file)?;
+                info!("importing background done!");
+                backgrounds.insert(background_file.clone(), background.clone());
+                background
+            }
+        },
+        None => RgbImage::new(1, 1),
+    };
+
+    Ok(Scene {
+        materials: scene_json.materials.clone(),
+        lights: scene_json.lights.clone(),
+        shapes,
+        background,
+        camera: scene_json.camera,
+        frame_width: scene_json.frame_width,
+        frame_height: scene_json.frame_height,
+        fov: -scene_json.fov_in_degrees * (PI / 180.0),
+        max_reflect_depth: scene_json.max_reflect_depth,
+        samples_per_pixel: scene_json.samples_per_pixel,
+        adaptive_sampling: scene_json.adaptive_sampling,
+        denoise: scene_json.denoise,
+        shutter: scene_json.shutter,
+        tiles: scene_json.tiles,
+        region: None,
+        description: scene_json,
+    })
 }
 
 impl SceneJson {
-    fn from_file(file_path: &str) -> SceneJson {
-        let file = File::open(file_path)
-            .unwrap_or_else(|err| panic!("failed to open file: {}: \"{}\"", file_path, err));
-        serde_json::from_reader(file)
-            .unwrap_or_else(|err| panic!("failed to parse file: {}: {}", file_path, err))
+    // what can't be caught when deserializing, the materials are checked when building the shapes
+    fn validate(&self) -> Result<(), SceneError> {
+        if self.frame_width == 0 || self.frame_height == 0 {
+            return Err(SceneError::Invalid(format!(
+                "the frame can't be {}x{}",
+                self.frame_width, self.frame_height
+            )));
+        }
+        if !(self.fov_in_degrees > 0.0 && self.fov_in_degrees < 180.0) {
+            return Err(SceneError::Invalid(format!(
+                "the field of view must be between 0 and 180 degrees, not {}",
+                self.fov_in_degrees
+            )));
+        }
+        if let Some(adaptive_sampling) = &self.adaptive_sampling {
+            if adaptive_sampling.max_samples < adaptive_sampling.min_samples {
+                return Err(SceneError::Invalid(format!(
+                    "the adaptive sampling can't take at most {} samples and at least {}",
+                    adaptive_sampling.max_samples, adaptive_sampling.min_samples
+                )));
+            }
+        }
+        // the eyes only converge with the perspective projection
+        if let Some(stereo) = &self.camera.stereo {
+            if stereo.convergence != Convergence::Parallel
+                && self.camera.projection != Projection::Perspective
+            {
+                return Err(SceneError::Invalid(format!(
+                    "the {:?} convergence needs the perspective projection, not {:?}",
+                    stereo.convergence, self.camera.projection
+                )));
+            }
+        }
+        let mut names = std::collections::HashSet::new();
+        for name in self.shapes.iter().filter_map(|shape| shape.get("name")) {
+            let name = name.as_str().ok_or_else(|| {
+                SceneError::Invalid(format!(
+                    "the name of a shape must be a string, not {}",
+                    name
+                ))
+            })?;
+            if name.parse::<usize>().is_ok() || !names.insert(name) {
+                return Err(SceneError::Invalid(format!(
+                    "the name of a shape must be unique and not a number, not \"{}\"",
+                    name
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// builds a scene from code, the same way it would be built from a scene file
+// e.g. `SceneBuilder::new(640, 480).add_material("red", red).add_sphere(center, 1.0, "red").build()`
+pub struct SceneBuilder {
+    scene_json: SceneJson,
+    duplicate_materials: Vec<String>,
+    shape_registry: ShapeRegistry,
+}
+
+impl SceneBuilder {
+    pub fn new(frame_width: usize, frame_height: usize) -> Self {
+        Self {
+            scene_json: SceneJson {
+                materials: HashMap::new(),
+                lights: Vec::new(),
+                shapes: Vec::new(),
+                background: None,
+                camera: Camera::default(),
+                frame_width,
+                frame_height,
+                fov_in_degrees: 60.0,
+                max_reflect_depth: 4,
+                samples_per_pixel: default_samples_per_pixel(),
+                adaptive_sampling: None,
+                denoise: None,
+                shutter: Shutter::default(),
+                tiles: TileOptions::default(),
+                animation: None,
+            },
+            duplicate_materials: Vec::new(),
+            shape_registry: ShapeRegistry::default(),
+        }
+    }
+
+    pub fn add_material(&mut self, name: &str, material: Material) -> &mut Self {
+        if self
+            .scene_json
+            .materials
+            .insert(name.to_string(), material)
+            .is_some()
+        {
+            self.duplicate_materials.push(name.to_string());
+        }
+        self
+    }
+
+    pub fn add_light(&mut self, light: Light) -> &mut Self {
+        self.scene_json.lights.push(light);
+        self
+    }
+
+    pub fn add_sphere(&mut self, center: Vector3<f32>, radius: f32, material: &str) -> &mut Self {
+        self.add_shape(tagged(
+            "sphere",
+            SphereJson {
+                center,
+                radius,
+                material: material.to_string(),
+                velocity: Vector3::zero(),
+            },
+        ))
+    }
+
+    pub fn add_plane(
+        &mut self,
+        point: Vector3<f32>,
+        normal: Vector3<f32>,
+        material: &str,
+    ) -> &mut Self {
+        self.add_shape(tagged(
+            "plane",
+            PlaneJson {
+                point,
+                normal,
+                material: material.to_string(),
+                velocity: Vector3::zero(),
+            },
+        ))
+    }
+
+    pub fn add_disk(
+        &mut self,
+        center: Vector3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        material: &str,
+    ) -> &mut Self {
+        self.add_shape(tagged(
+            "disk",
+            DiskJson {
+                center,
+                normal,
+                radius,
+                material: material.to_string(),
+                velocity: Vector3::zero(),
+            },
+        ))
+    }
+
+    pub fn add_checkboard_disk(
+        &mut self,
+        center: Vector3<f32>,
+        normal: Vector3<f32>,
+        radius: f32,
+        dist_between_mats: f32,
+        materials: (&str, &str),
+    ) -> &mut Self {
+        self.add_shape(tagged(
+            "checkboard_disk",
+            CheckBoardDiskJson {
+                center,
+                normal,
+                radius,
+                dist_between_mats,
+                material1: materials.0.to_string(),
+                material2: materials.1.to_string(),
+                velocity: Vector3::zero(),
+            },
+        ))
+    }
+
+    pub fn add_polygon(&mut self, vertices: [Vector3<f32>; 3], material: &str) -> &mut Self {
+        self.add_shape(tagged(
+            "polygon",
+            PolygonJson {
+                vertex_0: vertices[0],
+                vertex_1: vertices[1],
+                vertex_2: vertices[2],
+                material: material.to_string(),
+                velocity: Vector3::zero(),
+            },
+        ))
+    }
+
+    // the obj file is read when the scene is built
+    pub fn add_mesh_from_obj(
+        &mut self,
+        wavefront: &str,
+        material: &str,
+        transform: Transform,
+    ) -> &mut Self {
+        self.add_shape(tagged(
+            "obj",
+            ObjJson {
+                wavefront: wavefront.to_string(),
+                material: Some(material.to_string()),
+                transform,
+                velocity: Vector3::zero(),
+            },
+        ))
+    }
+
+    // a `{"type": "...", ...}` entry, built through the shape registry
+    pub fn add_shape(&mut self, shape: serde_json::Value) -> &mut Self {
+        self.scene_json.shapes.push(shape);
+        self
+    }
+
+    pub fn set_shape_registry(&mut self, shape_registry: ShapeRegistry) -> &mut Self {
+        self.shape_registry = shape_registry;
+        self
+    }
+
+    pub fn set_camera(&mut self, camera: Camera) -> &mut Self {
+        self.scene_json.camera = camera;
+        self
+    }
+
+    pub fn set_background(&mut self, background_file: &str) -> &mut Self {
+        self.scene_json.background = Some(background_file.to_string());
+        self
+    }
+
+    pub fn set_fov(&mut self, fov_in_degrees: f32) -> &mut Self {
+        self.scene_json.fov_in_degrees = fov_in_degrees;
+        self
+    }
+
+    pub fn set_max_reflect_depth(&mut self, max_reflect_depth: usize) -> &mut Self {
+        self.scene_json.max_reflect_depth = max_reflect_depth;
+        self
+    }
+
+    pub fn set_samples_per_pixel(&mut self, samples_per_pixel: usize) -> &mut Self {
+        self.scene_json.samples_per_pixel = samples_per_pixel;
+        self
+    }
+
+    pub fn set_denoise(&mut self, denoise: Option<DenoiseOptions>) -> &mut Self {
+        self.scene_json.denoise = denoise;
+        self
+    }
+
+    pub fn set_adaptive_sampling(
+        &mut self,
+        adaptive_sampling: Option<AdaptiveSampling>,
+    ) -> &mut Self {
+        self.scene_json.adaptive_sampling = adaptive_sampling;
+        self
+    }
+
+    pub fn build(&self) -> Result<Scene, SceneError> {
+        if let Some(name) = self.duplicate_materials.first() {
+            return Err(SceneError::Invalid(format!(
+                "material \"{}\" is added twice",
+                name
+            )));
+        }
+        build_scene(
+            self.scene_json.clone(),
+            "<builder>",
+            &self.shape_registry,
+            &mut HashMap::new(),
+            None,
+            &mut HashMap::new(),
+        )
+    }
+}
+
+// the velocity of the entries is handled by build_scene, for every shape type
+pub(crate) fn register_builtin_shapes(registry: &mut ShapeRegistry) {
+    registry
+        .register("sphere", |value, context| {
+            let sphere: SphereJson = parse_shape(value)?;
+            Ok(Box::new(sphere.into_sphere(context.materials)?))
+        })
+        .register("plane", |value, context| {
+            let plane: PlaneJson = parse_shape(value)?;
+            Ok(Box::new(plane.into_plane(context.materials)?))
+        })
+        .register("disk", |value, context| {
+            let disk: DiskJson = parse_shape(value)?;
+            Ok(Box::new(disk.into_disk(context.materials)?))
+        })
+        .register("checkboard_disk", |value, context| {
+            let disk: CheckBoardDiskJson = parse_shape(value)?;
+            Ok(Box::new(disk.into_checkboard_disk(context.materials)?))
+        })
+        .register("polygon", |value, context| {
+            let polygon: PolygonJson = parse_shape(value)?;
+            Ok(Box::new(polygon.into_polygon(context.materials)?))
+        })
+        .register("obj", |value, context| {
+            let obj: ObjJson = parse_shape(value)?;
+            Ok(Box::new(obj.into_mesh(context)?))
+        })
+        .register("gltf", |value, context| {
+            let gltf: GltfJson = parse_shape(value)?;
+            let material = match &gltf.material {
+                Some(material) => Some(context.material(material)?),
+                None => None,
+            };
+            Ok(Box::new(gltf_import::load_mesh(
+                &gltf.file,
+                &gltf.transform,
+                material,
+            )?))
+        });
+}
+
+fn into_moving(
+    shape: Box<dyn Shape + Sync>,
+    velocity: Vector3<f32>,
+    shutter: &Shutter,
+) -> Box<dyn Shape + Sync> {
+    if velocity.is_zero() {
+        shape
+    } else {
+        Box::new(Moving::new(shape, velocity, shutter))
     }
 }
 
+fn get_material(
+    materials: &HashMap<String, MaterialJson>,
+    name: &str,
+) -> Result<Material, SceneError> {
+    materials
+        .get(name)
+        .copied()
+        .ok_or_else(|| SceneError::UnknownMaterial(name.to_string()))
+}
+
 impl SphereJson {
-    fn into_sphere(self, materials: &HashMap<String, MaterialJson>) -> Sphere {
-        Sphere::new(self.center, self.radius, materials[&self.material])
+    fn into_sphere(self, materials: &HashMap<String, MaterialJson>) -> Result<Sphere, SceneError> {
+        Ok(Sphere::new(
+            self.center,
+            self.radius,
+            get_material(materials, &self.material)?,
+        ))
     }
 }
 
 impl PlaneJson {
-    fn into_plane(self, materials: &HashMap<String, MaterialJson>) -> Plane {
-        Plane::new(self.point, self.normal, materials[&self.material])
+    fn into_plane(self, materials: &HashMap<String, MaterialJson>) -> Result<Plane, SceneError> {
+        Ok(Plane::new(
+            self.point,
+            self.normal,
+            get_material(materials, &self.material)?,
+        ))
     }
 }
 
 impl DiskJson {
-    fn into_disk(self, materials: &HashMap<String, MaterialJson>) -> Disk {
-        Disk::new(
+    fn into_disk(self, materials: &HashMap<String, MaterialJson>) -> Result<Disk, SceneError> {
+        Ok(Disk::new(
             self.center,
             self.normal,
             self.radius,
-            materials[&self.material],
-        )
+            get_material(materials, &self.material)?,
+        ))
     }
 }
 
 impl CheckBoardDiskJson {
-    fn into_checkboard_disk(self, materials: &HashMap<String, MaterialJson>) -> CheckBoardDisk {
-        CheckBoardDisk::new(
+    fn into_checkboard_disk(
+        self,
+        materials: &HashMap<String, MaterialJson>,
+    ) -> Result<CheckBoardDisk, SceneError> {
+        Ok(CheckBoardDisk::new(
             self.center,
             self.normal,
             self.radius,
             self.dist_between_mats,
-            materials[&self.material1],
-            materials[&self.material2],
-        )
+            get_material(materials, &self.material1)?,
+            get_material(materials, &self.material2)?,
+        ))
     }
 }
 
 impl PolygonJson {
-    fn into_polygon(self, materials: &HashMap<String, MaterialJson>) -> Polygon {
-        Polygon::new(
+    fn into_polygon(
+        self,
+        materials: &HashMap<String, MaterialJson>,
+    ) -> Result<Polygon, SceneError> {
+        Ok(Polygon::new(
             self.vertex_0,
             self.vertex_1,
             self.vertex_2,
-            materials[&self.material],
-        )
+            get_material(materials, &self.material)?,
+        ))
     }
 }
 
 impl ObjJson {
-    fn into_mesh(self, materials: &HashMap<String, MaterialJson>) -> Mesh {
-        Mesh::from_wavefront_file(&self.wavefront, &materials[&self.material])
-            .expect("failed to import mesh")
+    fn into_mesh(self, context: &mut ShapeContext) -> Result<Mesh, SceneError> {
+        let material = match &self.material {
+            Some(material) => Some(context.material(material)?),
+            None => None,
+        };
+        let mesh_file = context.mesh_file(&self.wavefront)?;
+        Mesh::from_obj_with_bvh(
+            &mesh_file.obj,
+            material.as_ref(),
+            &self.transform,
+            mesh_file.bvh.clone(),
+        )
+        .map_err(|err| SceneError::Invalid(format!("{}: {}", self.wavefront, err)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::Vector4;
+
+    use crate::camera::{Stereo, StereoLayout};
+    use crate::test_dir::TestDir;
+
+    fn red() -> Material {
+        Material::new(
+            Vector4::new(0.9, 0.1, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            10.0,
+            1.0,
+        )
+    }
+
+    #[test]
+    fn test_builder() {
+        let scene = SceneBuilder::new(9, 9)
+            .add_material("red", red())
+            .add_light(Light::new(Vector3::new(0.0, 0.0, 0.0), 1.0))
+            .add_sphere(Vector3::new(0.0, 0.0, -10.0), 2.0, "red")
+            .build()
+            .unwrap();
+        let framebuffer = scene.render();
+
+        // the sphere is in the middle of the frame, on a black background
+        let center = framebuffer.buffer[4 * 9 + 4];
+        assert!(center.x > 0.5 && center.y < 0.2);
+        assert_eq!(framebuffer.buffer[0], Pixel::zero());
+    }
+
+    #[test]
+    fn test_builder_validation() {
+        let unknown_material = SceneBuilder::new(9, 9)
+            .add_sphere(Vector3::new(0.0, 0.0, -10.0), 2.0, "red")
+            .build();
+        assert!(matches!(
+            unknown_material,
+            Err(SceneError::UnknownMaterial(_))
+        ));
+
+        let duplicate_material = SceneBuilder::new(9, 9)
+            .add_material("red", red())
+            .add_material("red", red())
+            .build();
+        assert!(matches!(duplicate_material, Err(SceneError::Invalid(_))));
+
+        let empty_frame = SceneBuilder::new(0, 9).build();
+        assert!(matches!(empty_frame, Err(SceneError::Invalid(_))));
+
+        let fewer_max_samples = SceneBuilder::new(9, 9)
+            .set_adaptive_sampling(Some(AdaptiveSampling {
+                min_samples: 8,
+                max_samples: 4,
+                tolerance: 0.02,
+            }))
+            .build();
+        assert!(matches!(fewer_max_samples, Err(SceneError::Invalid(_))));
+
+        // adaptive sampling has no tiles to save
+        let mut adaptive = SceneBuilder::new(9, 9).build().unwrap();
+        adaptive.adaptive_sampling = Some(AdaptiveSampling::default());
+        let checkpoint = RenderOptions::default().with_checkpoint("unused.checkpoint");
+        assert!(matches!(
+            adaptive.render_with_options(&checkpoint),
+            Err(SceneError::Invalid(_))
+        ));
+        assert!(!std::path::Path::new("unused.checkpoint").exists());
+
+        let mut camera = Camera {
+            projection: Projection::Orthographic { view_width: 4.0 },
+            stereo: Some(Stereo {
+                interocular_distance: 0.1,
+                convergence: Convergence::ToeIn,
+                convergence_distance: 10.0,
+                layout: StereoLayout::SideBySide,
+            }),
+            ..Camera::default()
+        };
+        let converging = SceneBuilder::new(9, 9).set_camera(camera).build();
+        assert!(matches!(converging, Err(SceneError::Invalid(_))));
+        camera.stereo = camera.stereo.map(|stereo| Stereo {
+            convergence: Convergence::Parallel,
+            ..stereo
+        });
+        assert!(SceneBuilder::new(9, 9).set_camera(camera).build().is_ok());
+    }
+
+    #[test]
+    fn test_adaptive_max_samples() {
+        // no pixel is ever below the tolerance, they all get max_samples samples and no more
+        let adaptive_sampling = AdaptiveSampling {
+            min_samples: 4,
+            max_samples: 6,
+            tolerance: -1.0,
+        };
+        let scene = SceneBuilder::new(4, 4)
+            .add_material("red", red())
+            .add_sphere(Vector3::new(0.0, 0.0, -10.0), 2.0, "red")
+            .set_adaptive_sampling(Some(adaptive_sampling))
+            .build()
+            .unwrap();
+
+        let accumulator = scene
+            .render_adaptive(&adaptive_sampling, &RenderOptions::default())
+            .unwrap();
+        for index in 0..4 * 4 {
+            assert_eq!(accumulator.sample_count(index), 6);
+        }
+    }
+
+    #[test]
+    fn test_shape_layouts() {
+        let scene_json = |shapes: serde_json::Value| {
+            serde_json::from_value::<SceneJson>(serde_json::json!({
+                "materials": {},
+                "lights": [],
+                "shapes": shapes,
+                "frame_width": 4,
+                "frame_height": 4,
+                "fov_in_degrees": 60.0,
+                "max_reflect_depth": 1
+            }))
+        };
+        let sphere =
+            serde_json::json!({ "center": [0.0, 0.0, -1.0], "radius": 1.0, "material": "red" });
+        let plane = serde_json::json!({ "point": [0.0, -1.0, 0.0], "normal": [0.0, 1.0, 0.0], "material": "red" });
+
+        // the categories are optional and their shapes come in the order of the former layout
+        let legacy =
+            scene_json(serde_json::json!({ "planes": [plane], "spheres": [sphere] })).unwrap();
+        let list = scene_json(serde_json::json!([
+            { "type": "sphere", "center": [0.0, 0.0, -1.0], "radius": 1.0, "material": "red" },
+            { "type": "plane", "point": [0.0, -1.0, 0.0], "normal": [0.0, 1.0, 0.0], "material": "red" }
+        ]))
+        .unwrap();
+        assert_eq!(legacy.shapes, list.shapes);
+        assert!(scene_json(serde_json::json!({})).unwrap().shapes.is_empty());
+        assert!(scene_json(serde_json::json!({ "cubes": [] })).is_err());
+
+        let duplicate_names = scene_json(serde_json::json!([
+            { "type": "sphere", "name": "ball", "center": [0.0, 0.0, -1.0], "radius": 1.0, "material": "red" },
+            { "type": "sphere", "name": "ball", "center": [0.0, 0.0, -3.0], "radius": 1.0, "material": "red" }
+        ]))
+        .unwrap();
+        assert!(matches!(
+            duplicate_names.validate(),
+            Err(SceneError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn test_yaml_scene() {
+        let directory = TestDir::new("yaml_scene");
+        let path = directory.join("scene.yaml");
+        let yaml = format!(
+            "
+# vectors can be written as [x, y, z]
+include: [{}/materials/default.json]
+materials:
+  red:
+    albedo: [0.9, 0.1, 0.0, 0.0]
+    diffuse_color: [1.0, 0.0, 0.0]
+    specular_exponent: 10.0
+    refractive_index: 1.0
+lights:
+  - position: [0, 0, 0]
+    intensity: 1.0
+shapes:
+  - type: sphere
+    center: [0, 0, -10]
+    radius: 2.0
+    material: red
+frame_width: 9
+frame_height: 9
+fov_in_degrees: 60
+max_reflect_depth: 4
+",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        std::fs::write(&path, yaml).unwrap();
+        let scene = Scene::load(path.to_str().unwrap()).unwrap();
+        let built = SceneBuilder::new(9, 9)
+            .add_material("red", red())
+            .add_light(Light::new(Vector3::new(0.0, 0.0, 0.0), 1.0))
+            .add_sphere(Vector3::new(0.0, 0.0, -10.0), 2.0, "red")
+            .build()
+            .unwrap();
+
+        assert!(scene.materials.contains_key("ivory"));
+        assert_eq!(scene.render().buffer, built.render().buffer);
+    }
+
+    #[test]
+    fn test_override_paths() {
+        let directory = TestDir::new("override_paths");
+        let path = directory.file("scene.json");
+        std::fs::write(
+            &path,
+            r#"{ "materials": {}, "lights": [], "frame_width": 4, "frame_height": 4, "fov_in_degrees": 60.0, "max_reflect_depth": 1 }"#,
+        )
+        .unwrap();
+
+        let known = [
+            Override::new("frame_width", serde_json::json!(8)),
+            Override::new("denoise.iterations", serde_json::json!(2)),
+            Override::new("background", serde_json::Value::Null),
+        ];
+        assert!(Scene::load_with_overrides(&path, &known).is_ok());
+
+        let misspelled = [Override::new("frame_widht", serde_json::json!(8))];
+        assert!(matches!(
+            Scene::load_with_overrides(&path, &misspelled),
+            Err(SceneError::Override(_))
+        ));
+        let misspelled = [Override::new("camera.aperture_radus", serde_json::json!(1))];
+        assert!(matches!(
+            Scene::load_with_overrides(&path, &misspelled),
+            Err(SceneError::Override(_))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        // decoding the background of the scene is slow in debug builds
+        let directory = TestDir::new("round_trip");
+        let background = directory.join("background.png");
+        RgbImage::from_pixel(2, 2, Rgb([20, 40, 80]))
+            .save(&background)
+            .unwrap();
+        let overrides = [
+            Override::new(
+                "background",
+                serde_json::json!(background.to_str().unwrap()),
+            ),
+            Override::new("frame_width", serde_json::json!(16)),
+            Override::new("frame_height", serde_json::json!(12)),
+        ];
+        let mut scene = Scene::load_with_overrides("scene.json", &overrides).unwrap();
+        scene.samples_per_pixel = 1;
+        scene.lights[0].intensity = 2.5;
+
+        let saved = directory.file("saved.json");
+        scene.save(&saved).unwrap();
+        let reloaded = Scene::load(&saved).unwrap();
+
+        assert_eq!(reloaded.to_json().unwrap(), scene.to_json().unwrap());
+        assert_eq!(reloaded.lights[0].intensity, 2.5);
+        assert_eq!(reloaded.render().buffer, scene.render().buffer);
+        // the mesh is still a reference to its obj file
+        assert!(reloaded
+            .to_json()
+            .unwrap()
+            .contains("\"wavefront\": \"objs/duck.obj\""));
+
+        // the description follows the shapes
+        let shape_count = scene.shapes().len();
+        scene.remove_shape(0);
+        let saved_json = scene.to_json().unwrap();
+        let resaved = SceneFormat::Json.parse(&saved_json, "<scene>").unwrap();
+        assert_eq!(resaved["shapes"].as_array().unwrap().len(), shape_count - 1);
+        let sphere = Sphere::new(Vector3::new(0.0, 0.0, -5.0), 1.0, scene.materials["ivory"]);
+        scene.add_shape(Box::new(sphere));
+        assert!(scene.to_json().is_err());
     }
 }
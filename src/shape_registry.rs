@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::mesh_cache::{MeshCache, MeshFile};
+use crate::scene::{self, SceneError};
+use crate::shapes::material::Material;
+use crate::shapes::shape::Shape;
+use crate::wavefront::Obj;
+
+type ShapeDeserializer = Box<
+    dyn Fn(Value, &mut ShapeContext) -> Result<Box<dyn Shape + Sync>, SceneError> + Send + Sync,
+>;
+
+// turns `{"type": "my_shape", ...}` scene entries into shapes, by type tag
+// the built-in shapes are registered as "sphere", "plane", "disk", "checkboard_disk", "polygon", "obj" and "gltf"
+pub struct ShapeRegistry {
+    deserializers: HashMap<String, ShapeDeserializer>,
+}
+
+// what the deserializers can use from the scene being built
+pub struct ShapeContext<'a> {
+    pub(crate) materials: &'a HashMap<String, Material>,
+    pub(crate) objs: &'a mut HashMap<String, MeshFile>,
+    pub(crate) mesh_cache: Option<&'a MeshCache>,
+}
+
+impl Default for ShapeRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        scene::register_builtin_shapes(&mut registry);
+        registry
+    }
+}
+
+impl ShapeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // without the built-in shapes
+    pub fn empty() -> Self {
+        Self {
+            deserializers: HashMap::new(),
+        }
+    }
+
+    // a tag registered twice uses the last deserializer
+    pub fn register<F>(&mut self, tag: &str, deserializer: F) -> &mut Self
+    where
+        F: Fn(Value, &mut ShapeContext) -> Result<Box<dyn Shape + Sync>, SceneError>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.deserializers
+            .insert(tag.to_string(), Box::new(deserializer));
+        self
+    }
+
+    pub fn contains(&self, tag: &str) -> bool {
+        self.deserializers.contains_key(tag)
+    }
+
+    pub fn deserialize(
+        &self,
+        value: Value,
+        context: &mut ShapeContext,
+    ) -> Result<Box<dyn Shape + Sync>, SceneError> {
+        let tag = value
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or_else(|| SceneError::Invalid(format!("shape without a \"type\": {}", value)))?
+            .to_string();
+        let deserializer = self
+            .deserializers
+            .get(&tag)
+            .ok_or_else(|| SceneError::Invalid(format!("unknown shape type: \"{}\"", tag)))?;
+        deserializer(value, context)
+    }
+}
+
+impl ShapeContext<'_> {
+    pub fn material(&self, name: &str) -> Result<Material, SceneError> {
+        self.materials
+            .get(name)
+            .copied()
+            .ok_or_else(|| SceneError::UnknownMaterial(name.to_string()))
+    }
+
+    // mesh files (obj, ply or stl) are only read once per scene file
+    pub fn obj(&mut self, file_path: &str) -> Result<&Obj, SceneError> {
+        Ok(&self.mesh_file(file_path)?.obj)
+    }
+
+    // the mesh file with the bvh of its faces
+    pub fn mesh_file(&mut self, file_path: &str) -> Result<&MeshFile, SceneError> {
+        if !self.objs.contains_key(file_path) {
+            let mesh_file = MeshFile::read(file_path, self.mesh_cache)
+                .map_err(|err| SceneError::Io(file_path.to_string(), err))?;
+            self.objs.insert(file_path.to_string(), mesh_file);
+        }
+        Ok(&self.objs[file_path])
+    }
+}
+
+// deserializes the fields of a tagged shape entry, the "type" field is ignored
+pub fn parse_shape<T: DeserializeOwned>(value: Value) -> Result<T, SceneError> {
+    let tag = value
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+    serde_json::from_value(value).map_err(|err| SceneError::Parse(format!("{} shape", tag), err))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::{Vector3, Vector4};
+    use serde::Deserialize;
+
+    use crate::light::Light;
+    use crate::scene::SceneBuilder;
+    use crate::shapes::sphere::Sphere;
+
+    // a sphere given by its diameter, standing in for a shape of another crate
+    #[derive(Deserialize)]
+    struct BallJson {
+        center: Vector3<f32>,
+        diameter: f32,
+        material: String,
+    }
+
+    fn builder() -> SceneBuilder {
+        let mut builder = SceneBuilder::new(9, 9);
+        builder
+            .add_material(
+                "red",
+                Material::new(
+                    Vector4::new(0.9, 0.1, 0.0, 0.0),
+                    Vector3::new(1.0, 0.0, 0.0),
+                    10.0,
+                    1.0,
+                ),
+            )
+            .add_light(Light::new(Vector3::new(0.0, 0.0, 0.0), 1.0));
+        builder
+    }
+
+    #[test]
+    fn test_custom_shape() {
+        let mut registry = ShapeRegistry::new();
+        registry.register("ball", |value, context| {
+            let ball: BallJson = parse_shape(value)?;
+            Ok(Box::new(Sphere::new(
+                ball.center,
+                ball.diameter / 2.0,
+                context.material(&ball.material)?,
+            )))
+        });
+        let custom = builder()
+            .set_shape_registry(registry)
+            .add_shape(serde_json::json!({
+                "type": "ball",
+                "center": [0.0, 0.0, -10.0],
+                "diameter": 4.0,
+                "material": "red"
+            }))
+            .build()
+            .unwrap();
+        let builtin = builder()
+            .add_sphere(Vector3::new(0.0, 0.0, -10.0), 2.0, "red")
+            .build()
+            .unwrap();
+
+        assert_eq!(custom.render().buffer, builtin.render().buffer);
+    }
+
+    #[test]
+    fn test_builtin_tags() {
+        let tagged = builder()
+            .add_shape(serde_json::json!({
+                "type": "sphere",
+                "center": [0.0, 0.0, -10.0],
+                "radius": 2.0,
+                "material": "red"
+            }))
+            .build()
+            .unwrap();
+        let builtin = builder()
+            .add_sphere(Vector3::new(0.0, 0.0, -10.0), 2.0, "red")
+            .build()
+            .unwrap();
+        assert_eq!(tagged.render().buffer, builtin.render().buffer);
+
+        let unknown = builder()
+            .add_shape(serde_json::json!({"type": "ball"}))
+            .build();
+        assert!(matches!(unknown, Err(SceneError::Invalid(_))));
+    }
+}
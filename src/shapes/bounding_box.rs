@@ -0,0 +1,119 @@
+use crate::shapes::shape::Ray;
+
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+// axis aligned bounding box
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BoundingBox {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl BoundingBox {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    // the first point is given on its own so there is always one
+    pub fn from_points(first: Vector3<f32>, others: &[Vector3<f32>]) -> Self {
+        others
+            .iter()
+            .fold(BoundingBox::new(first, first), |bounding_box, point| {
+                bounding_box.union(&BoundingBox::new(*point, *point))
+            })
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox::new(
+            Vector3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            Vector3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        )
+    }
+
+    pub fn translate(&self, offset: Vector3<f32>) -> BoundingBox {
+        BoundingBox::new(self.min + offset, self.max + offset)
+    }
+
+    pub fn is_ray_intersecting(&self, ray: &Ray) -> bool {
+        self.ray_distance(ray).is_some()
+    }
+
+    // the distance along the ray to where it enters the box, 0 if it starts inside
+    pub fn ray_distance(&self, ray: &Ray) -> Option<f32> {
+        let tx_min = (self.min.x - ray.origin.x) * ray.inv_direction.x;
+        let tx_max = (self.max.x - ray.origin.x) * ray.inv_direction.x;
+        let ty_min = (self.min.y - ray.origin.y) * ray.inv_direction.y;
+        let ty_max = (self.max.y - ray.origin.y) * ray.inv_direction.y;
+        let tz_min = (self.min.z - ray.origin.z) * ray.inv_direction.z;
+        let tz_max = (self.max.z - ray.origin.z) * ray.inv_direction.z;
+
+        let tmin = tx_min
+            .min(tx_max)
+            .max(ty_min.min(ty_max))
+            .max(tz_min.min(tz_max));
+        let tmax = tx_min
+            .max(tx_max)
+            .min(ty_min.max(ty_max))
+            .min(tz_min.max(tz_max));
+
+        if tmax < 0.0 || tmin > tmax {
+            return None;
+        }
+        Some(tmin.max(0.0))
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) / 2.0
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_points() {
+        let bounding_box = BoundingBox::from_points(
+            Vector3::new(1.0, -2.0, 3.0),
+            &[Vector3::new(-1.0, 2.0, 0.0), Vector3::new(0.0, 0.0, 5.0)],
+        );
+
+        assert_eq!(bounding_box.min, Vector3::new(-1.0, -2.0, 0.0));
+        assert_eq!(bounding_box.max, Vector3::new(1.0, 2.0, 5.0));
+
+        let point = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(
+            BoundingBox::from_points(point, &[]),
+            BoundingBox::new(point, point)
+        );
+    }
+
+    #[test]
+    fn test_ray_intersect() {
+        let bounding_box =
+            BoundingBox::new(Vector3::new(-1.0, -1.0, -1.0), Vector3::new(1.0, 1.0, 1.0));
+
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(bounding_box.is_ray_intersecting(&ray));
+
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, 1.0));
+        assert!(!bounding_box.is_ray_intersecting(&ray));
+
+        let ray = Ray::new(Vector3::new(0.0, 3.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(!bounding_box.is_ray_intersecting(&ray));
+
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(bounding_box.ray_distance(&ray), Some(4.0));
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(bounding_box.ray_distance(&ray), Some(0.0));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::shapes::bounding_box::BoundingBox;
+use crate::shapes::shape::Ray;
+
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+// the primitives of a leaf are tested one by one
+const LEAF_SIZE: usize = 4;
+
+// a bounding volume hierarchy over primitives given by their bounding boxes
+// it only keeps their indices, so it can be built once for a mesh file and refitted to its transformed copies
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bvh {
+    // the root first, the children of a node always come after it
+    nodes: Vec<BvhNode>,
+    // the primitives, in the order of the leaves
+    indices: Vec<u32>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+struct BvhNode {
+    bounding_box: BoundingBox,
+    // a leaf has count primitives from first in indices, an inner node has its children at first and first + 1
+    first: u32,
+    count: u32,
+}
+
+impl Bvh {
+    // the primitives are split in two halves along the longest axis of their centers, until there are few of them
+    pub fn build(boxes: &[BoundingBox]) -> Self {
+        let mut indices: Vec<u32> = (0..boxes.len() as u32).collect();
+        let mut nodes = Vec::new();
+        if boxes.is_empty() {
+            return Self { nodes, indices };
+        }
+
+        let empty = BvhNode {
+            bounding_box: boxes[0],
+            first: 0,
+            count: 0,
+        };
+        nodes.push(empty);
+        // the nodes to fill, with their range of indices
+        let mut stack = vec![(0, 0, boxes.len())];
+        while let Some((node, start, end)) = stack.pop() {
+            let range = &mut indices[start..end];
+            let bounding_box = union(range.iter().map(|index| boxes[*index as usize]));
+            // a range always has primitives
+            let centers: Vec<Vector3<f32>> = range
+                .iter()
+                .map(|index| boxes[*index as usize].center())
+                .collect();
+            let centers = BoundingBox::from_points(centers[0], &centers[1..]);
+            let extent = centers.max - centers.min;
+            let axis = if extent.x >= extent.y && extent.x >= extent.z {
+                0
+            } else if extent.y >= extent.z {
+                1
+            } else {
+                2
+            };
+
+            // the centers can't be told apart
+            if range.len() <= LEAF_SIZE || extent[axis] <= 0.0 {
+                nodes[node] = BvhNode {
+                    bounding_box,
+                    first: start as u32,
+                    count: range.len() as u32,
+                };
+                continue;
+            }
+
+            let middle = range.len() / 2;
+            range.select_nth_unstable_by(middle, |a, b| {
+                let a = boxes[*a as usize].center()[axis];
+                let b = boxes[*b as usize].center()[axis];
+                a.total_cmp(&b)
+            });
+            let first = nodes.len();
+            nodes.push(empty);
+            nodes.push(empty);
+            nodes[node] = BvhNode {
+                bounding_box,
+                first: first as u32,
+                count: 0,
+            };
+            stack.push((first, start, start + middle));
+            stack.push((first + 1, start + middle, end));
+        }
+
+        Self { nodes, indices }
+    }
+
+    // the boxes of the nodes are computed again from the boxes of the primitives, e.g. once they have been moved
+    pub fn refit(&mut self, boxes: &[BoundingBox]) {
+        assert_eq!(boxes.len(), self.indices.len());
+        for node in (0..self.nodes.len()).rev() {
+            let BvhNode { first, count, .. } = self.nodes[node];
+            let (first, count) = (first as usize, count as usize);
+            self.nodes[node].bounding_box = if count > 0 {
+                union(
+                    self.indices[first..first + count]
+                        .iter()
+                        .map(|index| boxes[*index as usize]),
+                )
+            } else {
+                self.nodes[first]
+                    .bounding_box
+                    .union(&self.nodes[first + 1].bounding_box)
+            };
+        }
+    }
+
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.nodes.first().map(|root| root.bounding_box)
+    }
+
+    // the closest hit of the ray, intersect returns the distance along the ray to a primitive and what was hit
+    pub fn closest_hit<T, F: Fn(usize) -> Option<(f32, T)>>(
+        &self,
+        ray: &Ray,
+        intersect: F,
+    ) -> Option<T> {
+        let mut closest: Option<(f32, T)> = None;
+        let mut stack = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node) = stack.pop() {
+            let BvhNode {
+                bounding_box,
+                first,
+                count,
+            } = self.nodes[node];
+            match bounding_box.ray_distance(ray) {
+                Some(distance) if closest.as_ref().is_none_or(|(hit, _)| distance <= *hit) => {}
+                _ => continue,
+            }
+
+            let first = first as usize;
+            if count == 0 {
+                stack.push(first);
+                stack.push(first + 1);
+                continue;
+            }
+            for index in &self.indices[first..first + count as usize] {
+                if let Some((distance, hit)) = intersect(*index as usize) {
+                    if closest
+                        .as_ref()
+                        .is_none_or(|(closest, _)| distance < *closest)
+                    {
+                        closest = Some((distance, hit));
+                    }
+                }
+            }
+        }
+        closest.map(|(_, hit)| hit)
+    }
+}
+
+fn union(mut boxes: impl Iterator<Item = BoundingBox>) -> BoundingBox {
+    let first = boxes.next().expect("a node has at least one primitive");
+    boxes.fold(first, |a, b| a.union(&b))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn unit_box(center: Vector3<f32>) -> BoundingBox {
+        let half = Vector3::new(0.5, 0.5, 0.5);
+        BoundingBox::new(center - half, center + half)
+    }
+
+    #[test]
+    fn test_closest_hit() {
+        // a row of boxes along x
+        let centers: Vec<Vector3<f32>> = (0..20)
+            .map(|i| Vector3::new(i as f32 * 2.0, 0.0, 0.0))
+            .collect();
+        let boxes: Vec<BoundingBox> = centers.iter().map(|center| unit_box(*center)).collect();
+        let bvh = Bvh::build(&boxes);
+        assert!(bvh.nodes.len() > 1);
+
+        let intersect = |boxes: &[BoundingBox], ray: &Ray| {
+            bvh.closest_hit(ray, |i| {
+                boxes[i].ray_distance(ray).map(|distance| (distance, i))
+            })
+        };
+        let ray = Ray::new(Vector3::new(-10.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(intersect(&boxes, &ray), Some(0));
+        let ray = Ray::new(Vector3::new(100.0, 0.0, 0.0), Vector3::new(-1.0, 0.0, 0.0));
+        assert_eq!(intersect(&boxes, &ray), Some(19));
+        let ray = Ray::new(Vector3::new(14.0, 10.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(intersect(&boxes, &ray), Some(7));
+        let ray = Ray::new(Vector3::new(15.0, 10.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+        assert_eq!(intersect(&boxes, &ray), None);
+
+        // moved along y
+        let moved: Vec<BoundingBox> = centers
+            .iter()
+            .map(|center| unit_box(center + Vector3::new(0.0, 5.0, 0.0)))
+            .collect();
+        let mut refitted = bvh.clone();
+        refitted.refit(&moved);
+        assert_eq!(refitted.bounding_box().unwrap().min.y, 4.5);
+        let ray = Ray::new(Vector3::new(-10.0, 5.0, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(
+            refitted.closest_hit(&ray, |i| moved[i].ray_distance(&ray).map(|d| (d, i))),
+            Some(0)
+        );
+
+        assert_eq!(Bvh::build(&[]).closest_hit(&ray, |i| Some((0.0, i))), None);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::shapes::bounding_box::BoundingBox;
+use crate::shapes::disk::disk_bounding_box;
 use crate::shapes::material::Material;
 use crate::shapes::shape::{Ray, RayHit, Shape};
 
@@ -67,4 +69,8 @@ impl Shape for CheckBoardDisk {
             }
         }
     }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(disk_bounding_box(self.center, self.normal, self.radius))
+    }
 }
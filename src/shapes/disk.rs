@@ -1,7 +1,8 @@
+use crate::shapes::bounding_box::BoundingBox;
 use crate::shapes::material::Material;
 use crate::shapes::shape::{Ray, RayHit, Shape};
 
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Vector3};
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize)]
@@ -57,4 +58,19 @@ impl Shape for Disk {
             }
         }
     }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(disk_bounding_box(self.center, self.normal, self.radius))
+    }
+}
+
+pub fn disk_bounding_box(center: Vector3<f32>, normal: Vector3<f32>, radius: f32) -> BoundingBox {
+    // the disk extends on each axis by radius * sin(angle between the axis and the normal)
+    let normal = normal.normalize();
+    let extent = Vector3::new(
+        radius * (1.0 - normal.x * normal.x).max(0.0).sqrt(),
+        radius * (1.0 - normal.y * normal.y).max(0.0).sqrt(),
+        radius * (1.0 - normal.z * normal.z).max(0.0).sqrt(),
+    );
+    BoundingBox::new(center - extent, center + extent)
 }
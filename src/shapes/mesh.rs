@@ -1,17 +1,35 @@
-use crate::shapes::material::Material;
-use crate::shapes::polygon::Polygon;
+use std::sync::Arc;
+
+use crate::mtl::MtlMaterial;
+use crate::scene::SceneError;
+use crate::shapes::bounding_box::BoundingBox;
+use crate::shapes::bvh::Bvh;
+use crate::shapes::material::{Color, Material};
+use crate::shapes::polygon;
 use crate::shapes::shape::{Ray, RayHit, Shape};
+use crate::shapes::texture::Texture;
+use crate::shapes::transform::Transform;
 use crate::wavefront::Obj;
 
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Vector2, Vector3};
 use serde::{Deserialize, Serialize};
 
+// triangles indexing shared vertices and materials, rather than a polygon with its own copies for each of them
 #[derive(Serialize, Deserialize)]
 pub struct Mesh {
-    polygons: Vec<Polygon>,
-    bounding_min: Vector3<f32>,
-    bounding_max: Vector3<f32>,
-    material: Material,
+    vertexes: Vec<Vector3<f32>>,
+    // clockwise like the polygons
+    triangles: Vec<[u32; 3]>,
+    // one per vertex, or empty
+    normals: Vec<Vector3<f32>>,
+    colors: Vec<Color>,
+    // the texture coordinates of the corners of each triangle, or empty
+    uvs: Vec<[Vector2<f32>; 3]>,
+    materials: Vec<MeshMaterial>,
+    // the index in materials of each triangle
+    triangle_materials: Vec<u32>,
+    bounding_box: BoundingBox,
+    bvh: Bvh,
 
     //
     bb_miss: u32,
@@ -19,100 +37,501 @@ pub struct Mesh {
     mesh_hit: u32,
 }
 
+// a material shared by triangles of a mesh, the texture is used with the texture coordinates of the triangles
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MeshMaterial {
+    pub material: Material,
+    // not saved, the meshes are saved as references to their file
+    #[serde(skip)]
+    pub texture: Option<Arc<Texture>>,
+}
+
+impl MeshMaterial {
+    pub fn new(material: Material) -> Self {
+        Self {
+            material,
+            texture: None,
+        }
+    }
+}
+
 impl Mesh {
-    pub fn from_wavefront_file(
-        file_name: &str,
-        material: &Material,
-    ) -> Result<Mesh, std::io::Error> {
-        let obj = Obj::from_file(file_name)?;
-        let polygons: Vec<Polygon> = obj
-            .faces
-            .iter()
-            .map(|face| {
-                Polygon::new(
-                    obj.vertexes[face[0] - 1],
-                    obj.vertexes[face[1] - 1],
-                    obj.vertexes[face[2] - 1],
-                    *material,
-                )
-            })
-            .collect();
+    // triangles index vertexes and are clockwise, triangle_materials has the index in materials of each of them
+    // there must be at least one triangle
+    pub fn new(
+        vertexes: Vec<Vector3<f32>>,
+        triangles: Vec<[u32; 3]>,
+        materials: Vec<MeshMaterial>,
+        triangle_materials: Vec<u32>,
+    ) -> Result<Mesh, SceneError> {
+        check_indices(&vertexes, &triangles, &materials, &triangle_materials)?;
+        let bvh = Bvh::build(&triangle_boxes(&vertexes, &triangles));
+        Mesh::indexed(vertexes, triangles, materials, triangle_materials, bvh)
+    }
 
-        let (bounding_min, bounding_max) = Mesh::min_max_vertexes(&obj.vertexes);
+    // the indices have been checked
+    fn indexed(
+        vertexes: Vec<Vector3<f32>>,
+        triangles: Vec<[u32; 3]>,
+        materials: Vec<MeshMaterial>,
+        triangle_materials: Vec<u32>,
+        bvh: Bvh,
+    ) -> Result<Mesh, SceneError> {
+        let bounding_box = bvh
+            .bounding_box()
+            .ok_or_else(|| SceneError::Invalid("a mesh needs at least one triangle".to_string()))?;
 
         Ok(Mesh {
-            polygons,
-            bounding_min,
-            bounding_max,
-            material: *material,
+            vertexes,
+            triangles,
+            normals: Vec::new(),
+            colors: Vec::new(),
+            uvs: Vec::new(),
+            materials,
+            triangle_materials,
+            bounding_box,
+            bvh,
             bb_miss: 0,
             mesh_miss: 0,
             mesh_hit: 0,
         })
     }
 
-    fn min_max_vertexes(vertexes: &[Vector3<f32>]) -> (Vector3<f32>, Vector3<f32>) {
-        let ((min_x, max_x), (min_y, max_y), (min_z, max_z)) = vertexes
+    // smooth shading, one normal per vertex, they are normalized
+    pub fn with_normals(mut self, normals: Vec<Vector3<f32>>) -> Result<Self, SceneError> {
+        check_count("normals", normals.len(), "vertexes", self.vertexes.len())?;
+        self.normals = normals
+            .into_iter()
+            .map(|normal| normal.normalize())
+            .collect();
+        Ok(self)
+    }
+
+    // the diffuse color is multiplied by the colors of the vertices, one per vertex
+    pub fn with_colors(mut self, colors: Vec<Color>) -> Result<Self, SceneError> {
+        check_count("colors", colors.len(), "vertexes", self.vertexes.len())?;
+        self.colors = colors;
+        Ok(self)
+    }
+
+    // for the textures of the materials, the texture coordinates of the vertices of each triangle
+    pub fn with_uvs(mut self, uvs: Vec<[Vector2<f32>; 3]>) -> Result<Self, SceneError> {
+        check_count("uvs", uvs.len(), "triangles", self.triangles.len())?;
+        self.uvs = uvs;
+        Ok(self)
+    }
+
+    pub fn from_wavefront_file(file_name: &str, material: &Material) -> Result<Mesh, SceneError> {
+        let obj =
+            Obj::from_file(file_name).map_err(|err| SceneError::Io(file_name.to_string(), err))?;
+        Mesh::from_obj(&obj, Some(material), &Transform::default())
+    }
+
+    // material replaces the materials of the faces and their textures when given
+    pub fn from_obj(
+        obj: &Obj,
+        material: Option<&Material>,
+        transform: &Transform,
+    ) -> Result<Mesh, SceneError> {
+        let bvh = Bvh::build(&face_boxes(obj, &obj.vertexes));
+        Mesh::from_obj_with_bvh(obj, material, transform, bvh)
+    }
+
+    // bvh was built over the faces of obj, before the transform
+    pub fn from_obj_with_bvh(
+        obj: &Obj,
+        material: Option<&Material>,
+        transform: &Transform,
+        mut bvh: Bvh,
+    ) -> Result<Mesh, SceneError> {
+        let vertexes: Vec<Vector3<f32>> = obj
+            .vertexes
             .iter()
-            .map(|vertex| (vertex.x, vertex.y, vertex.z))
-            .fold(
-                (
-                    (vertexes[0].x, vertexes[0].x),
-                    (vertexes[0].y, vertexes[0].y),
-                    (vertexes[0].z, vertexes[0].z),
-                ),
-                |((min_x, max_x), (min_y, max_y), (min_z, max_z)), (x, y, z)| {
-                    (
-                        (min_x.min(x), max_x.max(x)),
-                        (min_y.min(y), max_y.max(y)),
-                        (min_z.min(z), max_z.max(z)),
-                    )
-                },
-            );
-
-        (
-            Vector3::new(min_x, min_y, min_z),
-            Vector3::new(max_x, max_y, max_z),
-        )
+            .map(|vertex| transform.apply_to_point(*vertex))
+            .collect();
+        let normals: Vec<Vector3<f32>> = obj
+            .normals
+            .iter()
+            .map(|normal| transform.apply_to_normal(*normal))
+            .collect();
+        // the faces are counter clockwise seen from outside, the triangles the other way around
+        // so that their normals point out of the mesh
+        let triangles: Vec<[u32; 3]> = obj
+            .faces
+            .iter()
+            .map(|face| [face[0] - 1, face[2] - 1, face[1] - 1].map(|index| index as u32))
+            .collect();
+
+        let (materials, triangle_materials, uvs) = match material {
+            Some(material) => (
+                vec![MeshMaterial::new(*material)],
+                vec![0; triangles.len()],
+                Vec::new(),
+            ),
+            None => {
+                let mut materials: Vec<MeshMaterial> = obj
+                    .materials
+                    .iter()
+                    .map(|mtl_material| MeshMaterial {
+                        material: mtl_material.to_material(),
+                        texture: mtl_material.diffuse_map.clone(),
+                    })
+                    .collect();
+                // for the faces without usemtl
+                let default_material = materials.len() as u32;
+                materials.push(MeshMaterial::new(MtlMaterial::new("").to_material()));
+                let triangle_materials = (0..triangles.len())
+                    .map(|i| match obj.face_materials.get(i).copied().flatten() {
+                        Some(face_material) => face_material as u32,
+                        None => default_material,
+                    })
+                    .collect();
+                let uvs = obj.uvs.iter().map(|uvs| [uvs[0], uvs[2], uvs[1]]).collect();
+                (materials, triangle_materials, uvs)
+            }
+        };
+
+        check_indices(&vertexes, &triangles, &materials, &triangle_materials)?;
+        bvh.refit(&face_boxes(obj, &vertexes));
+        Mesh::indexed(vertexes, triangles, materials, triangle_materials, bvh)?
+            .with_normals(normals)?
+            .with_colors(obj.colors.clone())?
+            .with_uvs(uvs)
     }
 
-    fn is_ray_intersecting_with_bounding_box(&self, ray: &Ray) -> bool {
-        let tx_min = (self.bounding_min.x - ray.origin.x) * ray.inv_direction.x;
-        let tx_max = (self.bounding_max.x - ray.origin.x) * ray.inv_direction.x;
-        let ty_min = (self.bounding_min.y - ray.origin.y) * ray.inv_direction.y;
-        let ty_max = (self.bounding_max.y - ray.origin.y) * ray.inv_direction.y;
-        let tz_min = (self.bounding_min.z - ray.origin.z) * ray.inv_direction.z;
-        let tz_max = (self.bounding_max.z - ray.origin.z) * ray.inv_direction.z;
+    fn triangle(&self, triangle: usize) -> [Vector3<f32>; 3] {
+        self.triangles[triangle].map(|index| self.vertexes[index as usize])
+    }
+
+    // interpolates values given per vertex at the barycentric coordinates (u, v) of a triangle
+    fn interpolate(
+        &self,
+        values: &[Vector3<f32>],
+        triangle: usize,
+        u: f32,
+        v: f32,
+    ) -> Vector3<f32> {
+        let [a, b, c] = self.triangles[triangle].map(|index| values[index as usize]);
+        a * (1.0 - u - v) + b * u + c * v
+    }
 
-        let tmin = tx_min
-            .min(tx_max)
-            .max(ty_min.min(ty_max))
-            .max(tz_min.min(tz_max));
-        let tmax = tx_min
-            .max(tx_max)
-            .min(ty_min.max(ty_max))
-            .min(tz_min.max(tz_max));
+    fn shade(&self, triangle: usize, u: f32, v: f32) -> Material {
+        let mesh_material = &self.materials[self.triangle_materials[triangle] as usize];
+        let mut material = mesh_material.material;
+        if !self.colors.is_empty() {
+            let color = self.interpolate(&self.colors, triangle, u, v);
+            material.diffuse_color = material.diffuse_color.zip(color, |a, b| a * b);
+        }
+        if let (Some(texture), Some(uvs)) = (&mesh_material.texture, self.uvs.get(triangle)) {
+            material = texture.shade(&material, uvs, u, v);
+        }
+        material
+    }
+}
 
-        !(tmax < 0.0 || tmin > tmax)
+// the triangles index vertexes and have a material in materials
+fn check_indices(
+    vertexes: &[Vector3<f32>],
+    triangles: &[[u32; 3]],
+    materials: &[MeshMaterial],
+    triangle_materials: &[u32],
+) -> Result<(), SceneError> {
+    if triangle_materials.len() != triangles.len() {
+        return Err(SceneError::Invalid(format!(
+            "a mesh with {} triangles has {} triangle materials",
+            triangles.len(),
+            triangle_materials.len()
+        )));
+    }
+    if let Some(index) = triangles
+        .iter()
+        .flatten()
+        .find(|index| **index as usize >= vertexes.len())
+    {
+        return Err(SceneError::Invalid(format!(
+            "mesh vertex index {} out of range, there are {}",
+            index,
+            vertexes.len()
+        )));
     }
+    if let Some(index) = triangle_materials
+        .iter()
+        .find(|index| **index as usize >= materials.len())
+    {
+        return Err(SceneError::Invalid(format!(
+            "mesh material index {} out of range, there are {}",
+            index,
+            materials.len()
+        )));
+    }
+    Ok(())
+}
+
+// one per element or none
+fn check_count(what: &str, count: usize, per: &str, expected: usize) -> Result<(), SceneError> {
+    if count != 0 && count != expected {
+        return Err(SceneError::Invalid(format!(
+            "a mesh with {} {} has {} {}",
+            expected, per, count, what
+        )));
+    }
+    Ok(())
+}
+
+// the boxes of the faces of obj, with its vertices moved to vertexes
+pub(crate) fn face_boxes(obj: &Obj, vertexes: &[Vector3<f32>]) -> Vec<BoundingBox> {
+    obj.faces
+        .iter()
+        .map(|face| {
+            BoundingBox::from_points(
+                vertexes[face[0] - 1],
+                &[vertexes[face[1] - 1], vertexes[face[2] - 1]],
+            )
+        })
+        .collect()
+}
+
+fn triangle_boxes(vertexes: &[Vector3<f32>], triangles: &[[u32; 3]]) -> Vec<BoundingBox> {
+    triangles
+        .iter()
+        .map(|triangle| {
+            let [a, b, c] = triangle.map(|index| vertexes[index as usize]);
+            BoundingBox::from_points(a, &[b, c])
+        })
+        .collect()
 }
 
 impl Shape for Mesh {
     fn ray_intersect(&self, ray: &Ray) -> Option<RayHit> {
         // check intersect with bounding box
-        if !self.is_ray_intersecting_with_bounding_box(ray) {
+        if !self.bounding_box.is_ray_intersecting(ray) {
             return None;
         }
 
-        // check intersect with polygons
-        self.polygons
-            .iter()
-            .filter_map(|polygon| polygon.ray_intersect(ray))
-            .min_by(|ray_hit_1, ray_hit_2| {
-                ray_hit_1
-                    .hit_dist
-                    .partial_cmp(&ray_hit_2.hit_dist)
-                    .expect("tried to compare to NaN")
-            })
+        // check intersect with triangles, only the closest one is shaded
+        let (hit_dist, triangle, u, v) = self.bvh.closest_hit(ray, |i| {
+            let [a, b, c] = self.triangle(i);
+            let (hit_dist, u, v) = polygon::intersect_triangle(a, b - a, c - a, ray)?;
+            Some((hit_dist, (hit_dist, i, u, v)))
+        })?;
+
+        let hit_normal = if self.normals.is_empty() {
+            let [a, b, c] = self.triangle(triangle);
+            (a - b).cross(c - b).normalize()
+        } else {
+            self.interpolate(&self.normals, triangle, u, v).normalize()
+        };
+        Some(RayHit {
+            hit_dist,
+            hit_point: ray.origin + ray.direction * hit_dist,
+            hit_normal,
+            material: self.shade(triangle, u, v),
+        })
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(self.bounding_box)
+    }
+
+    fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::{InnerSpace, Vector4};
+
+    use crate::ply;
+    use crate::shapes::material::Color;
+
+    #[test]
+    fn test_from_obj() {
+        // a counter clockwise square seen from +z, red on the left and blue on the right
+        let obj = ply::read(
+            b"ply
+format ascii 1.0
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+element face 1
+property list uchar int vertex_indices
+end_header
+-1 -1 0 255 0 0
+1 -1 0 0 0 255
+1 1 0 0 0 255
+-1 1 0 255 0 0
+4 0 1 2 3
+",
+        )
+        .unwrap();
+        let white = Material::new(
+            Vector4::new(1.0, 0.0, 0.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            1.0,
+            1.0,
+        );
+        let mesh = Mesh::from_obj(&obj, Some(&white), &Transform::default()).unwrap();
+
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let hit = mesh.ray_intersect(&ray).unwrap();
+
+        assert!((hit.hit_dist - 5.0).abs() < 1e-5);
+        // facing the ray
+        assert!((hit.hit_normal - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+        assert!((hit.material.diffuse_color - Color::new(0.5, 0.0, 0.5)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn test_face_materials() {
+        let mut obj = Obj::from_string(
+            "
+v -1 -1 0
+v 1 -1 0
+v 1 1 0
+v -1 1 0
+usemtl red
+f 1 2 3
+usemtl green
+f 1 3 4
+",
+        )
+        .unwrap();
+        obj.materials[0].diffuse = Color::new(1.0, 0.0, 0.0);
+        obj.materials[1].diffuse = Color::new(0.0, 1.0, 0.0);
+        let color_at = |mesh: &Mesh, x: f32, y: f32| {
+            let ray = Ray::new(Vector3::new(x, y, 5.0), Vector3::new(0.0, 0.0, -1.0));
+            mesh.ray_intersect(&ray).unwrap().material.diffuse_color
+        };
+
+        let mesh = Mesh::from_obj(&obj, None, &Transform::default()).unwrap();
+        assert_eq!(color_at(&mesh, 0.5, -0.5), Color::new(1.0, 0.0, 0.0));
+        assert_eq!(color_at(&mesh, -0.5, 0.5), Color::new(0.0, 1.0, 0.0));
+
+        // replaced by the scene material
+        let blue = Material::new(
+            Vector4::new(1.0, 0.0, 0.0, 0.0),
+            Color::new(0.0, 0.0, 1.0),
+            1.0,
+            1.0,
+        );
+        let mesh = Mesh::from_obj(&obj, Some(&blue), &Transform::default()).unwrap();
+        assert_eq!(color_at(&mesh, -0.5, 0.5), Color::new(0.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_triangle_size() {
+        // a triangle is three vertex indices and a material index, the vertexes are shared by about six triangles,
+        // where a polygon has its own copy of its vertices, edges, normal and material
+        let triangle = std::mem::size_of::<[u32; 3]>() + std::mem::size_of::<u32>();
+        let vertex = std::mem::size_of::<Vector3<f32>>();
+        let polygon = std::mem::size_of::<polygon::Polygon>();
+        assert_eq!(triangle, 16);
+        assert_eq!(polygon, 108);
+        assert!(polygon > 4 * (triangle + vertex / 2));
+    }
+
+    #[test]
+    fn test_indexed() {
+        // a square of two triangles sharing an edge, the second one textured
+        let vertexes = vec![
+            Vector3::new(-1.0, -1.0, 0.0),
+            Vector3::new(1.0, -1.0, 0.0),
+            Vector3::new(1.0, 1.0, 0.0),
+            Vector3::new(-1.0, 1.0, 0.0),
+        ];
+        let material =
+            |color: Color| Material::new(Vector4::new(1.0, 0.0, 0.0, 0.0), color, 1.0, 1.0);
+        let green = Color::new(0.0, 1.0, 0.0);
+        let textured = MeshMaterial {
+            material: material(Color::new(1.0, 1.0, 1.0)),
+            texture: Some(Arc::new(Texture::new(1, 1, vec![green]))),
+        };
+        let materials = vec![
+            MeshMaterial::new(material(Color::new(1.0, 0.0, 0.0))),
+            textured,
+        ];
+        let mesh = Mesh::new(
+            vertexes.clone(),
+            vec![[0, 2, 1], [0, 3, 2]],
+            materials.clone(),
+            vec![0, 1],
+        )
+        .unwrap()
+        .with_uvs(vec![[Vector2::new(0.0, 0.0); 3]; 2])
+        .unwrap();
+        assert_eq!(mesh.triangle_count(), 2);
+
+        let hit_at = |x: f32, y: f32| {
+            let ray = Ray::new(Vector3::new(x, y, 5.0), Vector3::new(0.0, 0.0, -1.0));
+            mesh.ray_intersect(&ray)
+        };
+        let hit = hit_at(0.5, -0.5).unwrap();
+        assert_eq!(hit.material.diffuse_color, Color::new(1.0, 0.0, 0.0));
+        assert!((hit.hit_normal - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+        assert_eq!(hit_at(-0.5, 0.5).unwrap().material.diffuse_color, green);
+        assert!(hit_at(1.5, 0.0).is_none());
+
+        // out of range indices, and attributes that don't match the vertexes or the triangles
+        let new = |triangles: Vec<[u32; 3]>, triangle_materials: Vec<u32>| {
+            Mesh::new(
+                vertexes.clone(),
+                triangles,
+                materials.clone(),
+                triangle_materials,
+            )
+        };
+        assert!(new(vec![[0, 4, 1]], vec![0]).is_err());
+        assert!(new(vec![[0, 2, 1]], vec![2]).is_err());
+        assert!(new(vec![[0, 2, 1]], vec![]).is_err());
+        assert!(new(vec![], vec![]).is_err());
+        let triangle = || new(vec![[0, 2, 1]], vec![0]).unwrap();
+        assert!(triangle().with_normals(vec![Vector3::unit_z(); 3]).is_err());
+        assert!(triangle().with_colors(vec![green; 4]).is_ok());
+        assert!(triangle()
+            .with_uvs(vec![[Vector2::new(0.0, 0.0); 3]; 2])
+            .is_err());
+    }
+
+    #[test]
+    fn test_obj_winding() {
+        // the faces of the duck are counter clockwise seen from outside, like in any obj file
+        let obj = Obj::from_file("objs/duck.obj").unwrap();
+        let white = Material::new(
+            Vector4::new(1.0, 0.0, 0.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            1.0,
+            1.0,
+        );
+        let mesh = Mesh::from_obj(&obj, Some(&white), &Transform::default()).unwrap();
+        let bounding_box = mesh.bounding_box().unwrap();
+        let center = bounding_box.center();
+        let distance = (bounding_box.max - bounding_box.min).magnitude();
+
+        // rays toward the center from all around, the faces they hit face them
+        let mut hits = 0;
+        for direction in [
+            Vector3::unit_x(),
+            -Vector3::unit_x(),
+            Vector3::unit_y(),
+            -Vector3::unit_y(),
+            Vector3::unit_z(),
+            -Vector3::unit_z(),
+            Vector3::new(1.0, 1.0, 1.0).normalize(),
+            Vector3::new(-1.0, 1.0, -1.0).normalize(),
+        ] {
+            let ray = Ray::new(center - direction * distance, direction);
+            if let Some(hit) = mesh.ray_intersect(&ray) {
+                assert!(hit.hit_normal.dot(direction) < 0.0);
+                hits += 1;
+            }
+        }
+        assert!(hits >= 6);
     }
 }
@@ -1,8 +1,13 @@
+pub mod bounding_box;
+pub mod bvh;
 pub mod checkboard_disk;
 pub mod disk;
 pub mod material;
 pub mod mesh;
+pub mod moving;
 pub mod plane;
 pub mod polygon;
 pub mod shape;
 pub mod sphere;
+pub mod texture;
+pub mod transform;
@@ -0,0 +1,95 @@
+use crate::shapes::bounding_box::BoundingBox;
+use crate::shapes::shape::{Ray, RayHit, Shape};
+
+use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
+
+// time interval during which the camera shutter is open, in frames
+// primary rays are given a random time in it, moving shapes are intersected at that time
+#[derive(Debug, Copy, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Shutter {
+    pub open: f32,
+    pub close: f32,
+}
+
+// a shape moving in a straight line, velocity is in units per frame
+pub struct Moving {
+    shape: Box<dyn Shape + Sync>,
+    velocity: Vector3<f32>,
+    bounding_box: Option<BoundingBox>,
+}
+
+impl Moving {
+    pub fn new(shape: Box<dyn Shape + Sync>, velocity: Vector3<f32>, shutter: &Shutter) -> Self {
+        // enclose the whole motion while the shutter is open
+        let bounding_box = shape.bounding_box().map(|bounding_box| {
+            bounding_box
+                .translate(velocity * shutter.open)
+                .union(&bounding_box.translate(velocity * shutter.close))
+        });
+
+        Self {
+            shape,
+            velocity,
+            bounding_box,
+        }
+    }
+}
+
+impl Shape for Moving {
+    fn ray_intersect(&self, ray: &Ray) -> Option<RayHit> {
+        // move the ray instead of the shape
+        let offset = self.velocity * ray.time;
+        let moved_ray = Ray::new_at_time(ray.origin - offset, ray.direction, ray.time);
+
+        self.shape.ray_intersect(&moved_ray).map(|ray_hit| RayHit {
+            hit_point: ray_hit.hit_point + offset,
+            ..ray_hit
+        })
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        self.bounding_box
+    }
+
+    fn triangle_count(&self) -> usize {
+        self.shape.triangle_count()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shapes::material::{Albedo, Color, Material};
+    use crate::shapes::sphere::Sphere;
+    use num::Zero;
+
+    #[test]
+    fn test_moving_sphere() {
+        let material = Material::new(Albedo::zero(), Color::zero(), 0.0, 0.0);
+        let sphere = Sphere::new(Vector3::new(0.0, 0.0, -5.0), 1.0, material);
+        let shutter = Shutter {
+            open: 0.0,
+            close: 1.0,
+        };
+        let moving = Moving::new(Box::new(sphere), Vector3::new(4.0, 0.0, 0.0), &shutter);
+
+        let ray = Ray::new_at_time(Vector3::zero(), Vector3::new(0.0, 0.0, -1.0), 0.0);
+        assert!(moving.ray_intersect(&ray).is_some());
+
+        let ray = Ray::new_at_time(Vector3::zero(), Vector3::new(0.0, 0.0, -1.0), 1.0);
+        assert!(moving.ray_intersect(&ray).is_none());
+
+        let ray = Ray::new_at_time(
+            Vector3::new(4.0, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, -1.0),
+            1.0,
+        );
+        let ray_hit = moving.ray_intersect(&ray).unwrap();
+        assert_eq!(ray_hit.hit_point, Vector3::new(4.0, 0.0, -4.0));
+
+        let bounding_box = moving.bounding_box().unwrap();
+        assert_eq!(bounding_box.min, Vector3::new(-1.0, -1.0, -6.0));
+        assert_eq!(bounding_box.max, Vector3::new(5.0, 1.0, -4.0));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::shapes::bounding_box::BoundingBox;
 use crate::shapes::material::Material;
 use crate::shapes::shape::{Ray, RayHit, Shape};
 
@@ -28,37 +29,59 @@ impl Polygon {
             vertex_0,
             vertex_1,
             vertex_2,
-            normal: (vertex_0 - vertex_1).cross(vertex_2 - vertex_1),
+            normal: (vertex_0 - vertex_1).cross(vertex_2 - vertex_1).normalize(),
             v0v1: vertex_1 - vertex_0,
             v0v2: vertex_2 - vertex_0,
             material,
         }
     }
+
+    // the distance along the ray and the barycentric coordinates (u, v) of the hit
+    // the hit point is vertex_0 * (1 - u - v) + vertex_1 * u + vertex_2 * v
+    pub fn intersect(&self, ray: &Ray) -> Option<(f32, f32, f32)> {
+        intersect_triangle(self.vertex_0, self.v0v1, self.v0v2, ray)
+    }
 }
 
-impl Shape for Polygon {
-    fn ray_intersect(&self, ray: &Ray) -> Option<RayHit> {
-        let pvec = ray.direction.cross(self.v0v2);
-        let det = self.v0v1.dot(pvec);
-        if det.abs() < 1e-3 {
-            return None;
-        }
+// möller-trumbore, for the triangle at vertex_0 with the edges v0v1 and v0v2
+pub fn intersect_triangle(
+    vertex_0: Vector3<f32>,
+    v0v1: Vector3<f32>,
+    v0v2: Vector3<f32>,
+    ray: &Ray,
+) -> Option<(f32, f32, f32)> {
+    let pvec = ray.direction.cross(v0v2);
+    let det = v0v1.dot(pvec);
+    // the ray is parallel to the triangle
+    if det.abs() < f32::EPSILON * v0v1.magnitude() * v0v2.magnitude() {
+        return None;
+    }
 
-        let inv_det = 1.0 / det;
+    let inv_det = 1.0 / det;
 
-        let tvec = ray.origin - self.vertex_0;
+    let tvec = ray.origin - vertex_0;
+    let u = tvec.dot(pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
 
-        let hit_dist = cgmath::dot(tvec, pvec) * inv_det;
-        if !(0.0..=1.0).contains(&hit_dist) {
-            return None;
-        }
+    let qvec = tvec.cross(v0v1);
+    let v = ray.direction.dot(qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
 
-        let qvec = tvec.cross(self.v0v1);
-        let v = ray.direction.dot(qvec) * inv_det;
-        if v < 0.0 || v + hit_dist > 1.0 {
-            return None;
-        }
+    let hit_dist = v0v2.dot(qvec) * inv_det;
+    if hit_dist < 1e-4 {
+        return None;
+    }
+
+    Some((hit_dist, u, v))
+}
 
+impl Shape for Polygon {
+    fn ray_intersect(&self, ray: &Ray) -> Option<RayHit> {
+        let (hit_dist, _, _) = self.intersect(ray)?;
         Some(RayHit {
             hit_dist,
             hit_point: ray.origin + ray.direction * hit_dist,
@@ -66,4 +89,59 @@ impl Shape for Polygon {
             material: self.material,
         })
     }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(BoundingBox::from_points(
+            self.vertex_0,
+            &[self.vertex_1, self.vertex_2],
+        ))
+    }
+
+    fn triangle_count(&self) -> usize {
+        1
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::Vector4;
+
+    use crate::shapes::material::Color;
+
+    #[test]
+    fn test_ray_intersect() {
+        // a large clockwise triangle in the plane z = -2, facing the origin
+        let material = Material::new(
+            Vector4::new(1.0, 0.0, 0.0, 0.0),
+            Color::new(1.0, 1.0, 1.0),
+            1.0,
+            1.0,
+        );
+        let polygon = Polygon::new(
+            Vector3::new(-10.0, -10.0, -2.0),
+            Vector3::new(0.0, 10.0, -2.0),
+            Vector3::new(10.0, -10.0, -2.0),
+            material,
+        );
+
+        // the distance is the one along the ray, not a barycentric coordinate
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+        let hit = polygon.ray_intersect(&ray).unwrap();
+        assert!((hit.hit_dist - 2.0).abs() < 1e-5);
+        assert!((hit.hit_point - Vector3::new(0.0, 0.0, -2.0)).magnitude() < 1e-5);
+        // the normal has a unit length whatever the size of the triangle
+        assert!((hit.hit_normal - Vector3::new(0.0, 0.0, 1.0)).magnitude() < 1e-5);
+
+        // further away than the side of the triangle
+        let ray = Ray::new(Vector3::new(1.0, 1.0, 30.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!((polygon.ray_intersect(&ray).unwrap().hit_dist - 32.0).abs() < 1e-4);
+
+        // behind the ray, and beside the triangle
+        let ray = Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(0.0, 0.0, 1.0));
+        assert!(polygon.ray_intersect(&ray).is_none());
+        let ray = Ray::new(Vector3::new(0.0, 20.0, 0.0), Vector3::new(0.0, 0.0, -1.0));
+        assert!(polygon.ray_intersect(&ray).is_none());
+    }
 }
@@ -1,3 +1,4 @@
+use crate::shapes::bounding_box::BoundingBox;
 use crate::shapes::material::Material;
 use cgmath::Vector3;
 
@@ -5,6 +6,8 @@ pub struct Ray {
     pub origin: Vector3<f32>,
     pub direction: Vector3<f32>,
     pub inv_direction: Vector3<f32>,
+    // when the ray was cast, used for motion blur
+    pub time: f32,
 }
 
 pub struct RayHit {
@@ -17,14 +20,29 @@ pub struct RayHit {
 pub trait Shape {
     // returns the distance from orig on ray_dir of the first intersection if any
     fn ray_intersect(&self, ray: &Ray) -> Option<RayHit>;
+
+    // returns the box enclosing the shape, None if the shape is unbounded
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
+
+    // returns the number of triangles the shape is made of
+    fn triangle_count(&self) -> usize {
+        0
+    }
 }
 
 impl Ray {
     pub fn new(origin: Vector3<f32>, direction: Vector3<f32>) -> Ray {
+        Ray::new_at_time(origin, direction, 0.0)
+    }
+
+    pub fn new_at_time(origin: Vector3<f32>, direction: Vector3<f32>, time: f32) -> Ray {
         Ray {
             origin,
             direction,
             inv_direction: 1.0 / direction,
+            time,
         }
     }
 }
@@ -1,3 +1,4 @@
+use crate::shapes::bounding_box::BoundingBox;
 use crate::shapes::material::Material;
 use crate::shapes::shape::{Ray, RayHit, Shape};
 
@@ -50,6 +51,12 @@ impl Shape for Sphere {
             })
         }
     }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        // radius can be negative in scene files
+        let extent = Vector3::new(self.radius, self.radius, self.radius).map(f32::abs);
+        Some(BoundingBox::new(self.center - extent, self.center + extent))
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,94 @@
+use cgmath::Vector2;
+
+use crate::shapes::material::{Color, Material};
+
+// an image mapped on triangles, the colors are used as they are stored like the background
+pub struct Texture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Texture {
+    // pixels line by line from the top left corner, in [0, 1]
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    // bilinear filtering, the texture repeats outside of [0, 1]
+    pub fn sample(&self, uv: Vector2<f32>) -> Color {
+        let x = uv.x.rem_euclid(1.0) * self.width as f32 - 0.5;
+        let y = uv.y.rem_euclid(1.0) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+
+        let pixel = |x: f32, y: f32| {
+            let x = (x as isize).rem_euclid(self.width as isize) as usize;
+            let y = (y as isize).rem_euclid(self.height as isize) as usize;
+            self.pixels[y * self.width + x]
+        };
+        let top = pixel(x0, y0) * (1.0 - fx) + pixel(x0 + 1.0, y0) * fx;
+        let bottom = pixel(x0, y0 + 1.0) * (1.0 - fx) + pixel(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    // the diffuse color is multiplied by the texture at the barycentric coordinates (u, v) of a triangle
+    // whose vertices have the texture coordinates uvs
+    pub fn shade(&self, material: &Material, uvs: &[Vector2<f32>; 3], u: f32, v: f32) -> Material {
+        let uv = uvs[0] * (1.0 - u - v) + uvs[1] * u + uvs[2] * v;
+        let color = self.sample(uv);
+        Material {
+            diffuse_color: material.diffuse_color.zip(color, |a, b| a * b),
+            ..*material
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::Vector3;
+
+    #[test]
+    fn test_sample() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        let texture = Texture::new(2, 1, vec![black, white]);
+
+        // on the pixel centers
+        assert_eq!(texture.sample(Vector2::new(0.25, 0.5)), black);
+        assert_eq!(texture.sample(Vector2::new(0.75, 0.5)), white);
+        // half way, and repeated
+        assert_eq!(
+            texture.sample(Vector2::new(0.5, 0.5)),
+            Color::new(0.5, 0.5, 0.5)
+        );
+        assert_eq!(texture.sample(Vector2::new(1.75, -0.5)), white);
+
+        let uvs = [
+            Vector2::new(0.25, 0.5),
+            Vector2::new(0.75, 0.5),
+            Vector2::new(0.25, 0.5),
+        ];
+        let material = Material::new(
+            cgmath::Vector4::new(1.0, 0.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.5, 0.0),
+            10.0,
+            1.0,
+        );
+        assert_eq!(
+            texture.shade(&material, &uvs, 1.0, 0.0).diffuse_color,
+            Vector3::new(1.0, 0.5, 0.0)
+        );
+        assert_eq!(
+            texture.shade(&material, &uvs, 0.0, 1.0).diffuse_color,
+            black
+        );
+    }
+}
@@ -0,0 +1,75 @@
+use cgmath::{Deg, Euler, InnerSpace, Matrix3, Vector3};
+use serde::{Deserialize, Serialize};
+
+// scale, then rotate (euler angles in degrees), then translate
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Transform {
+    pub translation: Vector3<f32>,
+    pub rotation: Vector3<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Vector3::new(0.0, 0.0, 0.0),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+        }
+    }
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::default()
+    }
+
+    pub fn apply_to_point(&self, point: Vector3<f32>) -> Vector3<f32> {
+        self.rotation_matrix() * point.zip(self.scale, |a, b| a * b) + self.translation
+    }
+
+    // normals are scaled by the inverse of the scale to stay perpendicular to the surface
+    pub fn apply_to_normal(&self, normal: Vector3<f32>) -> Vector3<f32> {
+        (self.rotation_matrix() * normal.zip(self.scale, |a, b| a / b)).normalize()
+    }
+
+    fn rotation_matrix(&self) -> Matrix3<f32> {
+        Matrix3::from(Euler::new(
+            Deg(self.rotation.x),
+            Deg(self.rotation.y),
+            Deg(self.rotation.z),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_apply_to_point() {
+        let transform = Transform {
+            translation: Vector3::new(1.0, 0.0, 0.0),
+            rotation: Vector3::new(0.0, 90.0, 0.0),
+            scale: Vector3::new(2.0, 2.0, 2.0),
+        };
+
+        let point = transform.apply_to_point(Vector3::new(1.0, 0.0, 0.0));
+
+        assert!((point - Vector3::new(1.0, 0.0, -2.0)).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn test_apply_to_normal() {
+        let transform = Transform {
+            scale: Vector3::new(2.0, 1.0, 1.0),
+            ..Default::default()
+        };
+
+        // the normal of the x + y = 1 plane, which becomes x / 2 + y = 1
+        let normal = transform.apply_to_normal(Vector3::new(1.0, 1.0, 0.0));
+
+        assert!((normal - Vector3::new(1.0, 2.0, 0.0).normalize()).magnitude() < 1e-5);
+    }
+}
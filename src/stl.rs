@@ -0,0 +1,137 @@
+use std::convert::TryInto;
+use std::io::{Error, ErrorKind};
+
+use cgmath::Vector3;
+
+use crate::wavefront::Obj;
+
+// a stl parser, ascii or binary
+// every triangle has its own vertices, the facet normals are computed again from the vertices
+pub fn read(data: &[u8]) -> Result<Obj, Error> {
+    let vertexes = if is_binary(data) {
+        read_binary(data)?
+    } else {
+        read_ascii(data)?
+    };
+
+    if vertexes.is_empty() {
+        return Err(invalid("no facets"));
+    }
+
+    let faces = (0..vertexes.len() / 3)
+        .map(|i| Vector3::new(3 * i + 1, 3 * i + 2, 3 * i + 3))
+        .collect();
+    Ok(Obj {
+        vertexes,
+        faces,
+        ..Obj::default()
+    })
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid stl file: {}", message),
+    )
+}
+
+// binary files may also start with "solid", their size tells them apart
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < 84 {
+        return !data.starts_with(b"solid");
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    data.len() == 84 + count * 50 || !data.starts_with(b"solid")
+}
+
+// an 80 bytes header, the number of triangles,
+// then for each triangle its normal, its vertices and an attribute byte count
+fn read_binary(data: &[u8]) -> Result<Vec<Vector3<f32>>, Error> {
+    if data.len() < 84 {
+        return Err(invalid("unexpected end of file"));
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    let triangles = &data[84..];
+    if triangles.len() < count * 50 {
+        return Err(invalid("unexpected end of file"));
+    }
+
+    let value = |bytes: &[u8]| f32::from_le_bytes(bytes[..4].try_into().unwrap());
+    Ok(triangles
+        .chunks_exact(50)
+        .take(count)
+        .flat_map(|triangle| {
+            (1..4).map(move |i| {
+                let vertex = &triangle[i * 12..];
+                Vector3::new(value(vertex), value(&vertex[4..]), value(&vertex[8..]))
+            })
+        })
+        .collect())
+}
+
+fn read_ascii(data: &[u8]) -> Result<Vec<Vector3<f32>>, Error> {
+    let text = std::str::from_utf8(data).map_err(|err| invalid(&err.to_string()))?;
+    let mut vertexes = Vec::new();
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_ascii_whitespace().collect();
+        if let ["vertex", x, y, z] = words.as_slice() {
+            let parse = |word: &str| word.parse::<f32>().map_err(|_| invalid(line.trim()));
+            vertexes.push(Vector3::new(parse(x)?, parse(y)?, parse(z)?));
+        }
+    }
+    if vertexes.len() % 3 != 0 {
+        return Err(invalid("a facet doesn't have 3 vertices"));
+    }
+    Ok(vertexes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_ascii() {
+        let obj = read(
+            b"solid triangle
+  facet normal 0 0 1
+    outer loop
+      vertex 0 0 0
+      vertex 1 0 0
+      vertex 0 1 0
+    endloop
+  endfacet
+endsolid triangle
+",
+        )
+        .unwrap();
+
+        assert_eq!(obj.vertexes[1], Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(obj.faces, vec![Vector3::new(1, 2, 3)]);
+
+        let err = read(b"solid empty\nendsolid empty\n").err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_binary() {
+        // a header starting like an ascii file
+        let mut data = b"solid but binary".to_vec();
+        data.resize(80, 0);
+        data.extend(2u32.to_le_bytes());
+        for triangle in 0..2 {
+            for value in [0.0f32, 0.0, 1.0] {
+                data.extend(value.to_le_bytes());
+            }
+            for value in [0.0f32, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0, triangle as f32] {
+                data.extend(value.to_le_bytes());
+            }
+            data.extend(0u16.to_le_bytes());
+        }
+
+        let obj = read(&data).unwrap();
+
+        assert_eq!(obj.vertexes.len(), 6);
+        assert_eq!(obj.vertexes[5], Vector3::new(0.0, 1.0, 1.0));
+        assert_eq!(obj.faces[1], Vector3::new(4, 5, 6));
+    }
+}
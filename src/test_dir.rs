@@ -0,0 +1,37 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// a directory for the files of a test, removed with everything in it when dropped
+// the process id keeps the runs of the tests apart, e.g. when several checkouts are tested at once
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("tinygraph_x_test_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, name: P) -> PathBuf {
+        self.path.join(name)
+    }
+
+    // the path of a file of the directory, as the file names are given to the loaders
+    pub fn file(&self, name: &str) -> String {
+        self.join(name).to_str().unwrap().to_string()
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
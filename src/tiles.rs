@@ -0,0 +1,393 @@
+use std::convert::TryInto;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::scene::Pixel;
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TileOptions {
+    pub size: usize,
+    pub order: TileOrder,
+}
+
+impl Default for TileOptions {
+    fn default() -> Self {
+        Self {
+            size: 32,
+            order: TileOrder::Spiral,
+        }
+    }
+}
+
+// the order the tiles are handed to the rendering threads
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TileOrder {
+    Scanline,
+    // from the center of the image outwards
+    Spiral,
+    // follows a hilbert curve, neighbouring tiles are rendered close in time
+    Hilbert,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+
+    // framebuffer indices of the pixels of the tile, line by line
+    pub fn pixel_indices(&self, frame_width: usize) -> impl Iterator<Item = usize> + '_ {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| y * frame_width + x))
+    }
+}
+
+// a window of the framebuffer, x1 and y1 excluded
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Region {
+    pub x0: usize,
+    pub y0: usize,
+    pub x1: usize,
+    pub y1: usize,
+}
+
+impl Region {
+    pub fn full(width: usize, height: usize) -> Self {
+        Self {
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> usize {
+        self.y1 - self.y0
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x0 && x < self.x1 && y >= self.y0 && y < self.y1
+    }
+}
+
+// splits the region in tiles, the ones on the right and bottom edges may be smaller
+pub fn split_region(region: &Region, options: &TileOptions) -> Vec<Tile> {
+    let size = options.size.max(1);
+    let (width, height) = (region.width(), region.height());
+    let columns = width.div_ceil(size);
+    let rows = height.div_ceil(size);
+
+    let mut cells: Vec<(usize, usize)> = (0..rows)
+        .flat_map(|row| (0..columns).map(move |column| (column, row)))
+        .collect();
+    match options.order {
+        TileOrder::Scanline => {}
+        TileOrder::Spiral => {
+            let center_x = (columns as f32 - 1.0) / 2.0;
+            let center_y = (rows as f32 - 1.0) / 2.0;
+            // ring by ring, going around each ring
+            let key = |&(column, row): &(usize, usize)| {
+                let dx = column as f32 - center_x;
+                let dy = row as f32 - center_y;
+                (dx.abs().max(dy.abs()), dy.atan2(dx))
+            };
+            cells.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+        }
+        TileOrder::Hilbert => {
+            let side = columns.max(rows).next_power_of_two();
+            cells.sort_by_key(|&(column, row)| hilbert_index(side, column, row));
+        }
+    }
+
+    cells
+        .into_iter()
+        .map(|(column, row)| Tile {
+            x: region.x0 + column * size,
+            y: region.y0 + row * size,
+            width: size.min(width - column * size),
+            height: size.min(height - row * size),
+        })
+        .collect()
+}
+
+// distance along the hilbert curve filling a side x side square, side being a power of two
+fn hilbert_index(side: usize, mut x: usize, mut y: usize) -> usize {
+    let mut index = 0;
+    let mut s = side / 2;
+    while s > 0 {
+        let rx = (x & s > 0) as usize;
+        let ry = (y & s > 0) as usize;
+        index += s * s * ((3 * rx) ^ ry);
+        // rotate the quadrant so the curve stays continuous
+        if ry == 0 {
+            if rx == 1 {
+                x = side - 1 - x;
+                y = side - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    index
+}
+
+// a rendered tile, index is its position in the tile list
+#[derive(Serialize, Deserialize)]
+pub struct FinishedTile {
+    pub index: usize,
+    pub color: Vec<Pixel>,
+    pub albedo: Vec<Pixel>,
+    pub normal: Vec<Pixel>,
+}
+
+// what a checkpoint was made for, it is only resumed by the same render
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CheckpointHeader {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub tiles: TileOptions,
+    pub region: Region,
+    // of the scene description once the overrides are applied, any other change to the scene makes another render
+    pub scene_hash: u64,
+}
+
+// append only file of the finished tiles of a render:
+// a json header line, then for each tile its index and its pixels as little endian numbers
+pub struct Checkpoint {
+    writer: BufWriter<File>,
+}
+
+impl Checkpoint {
+    // returns the tiles finished by a previous run, a checkpoint made for another render is discarded
+    pub fn open(
+        path: &str,
+        header: &CheckpointHeader,
+        tiles: &[Tile],
+    ) -> io::Result<(Self, Vec<FinishedTile>)> {
+        let finished = match File::open(path) {
+            Ok(file) => read_tiles(BufReader::new(file), header, tiles)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        // re-written from scratch so a tile cut in half by an interruption is dropped,
+        // next to the checkpoint first so an interruption now doesn't lose the finished tiles
+        let partial = format!("{}.tmp", path);
+        let mut checkpoint = Checkpoint {
+            writer: BufWriter::new(File::create(&partial)?),
+        };
+        serde_json::to_writer(&mut checkpoint.writer, header)?;
+        checkpoint.writer.write_all(b"\n")?;
+        for tile in &finished {
+            checkpoint.write_tile(tile)?;
+        }
+        checkpoint.writer.flush()?;
+        drop(checkpoint);
+        fs::rename(&partial, path)?;
+
+        let checkpoint = Checkpoint {
+            writer: BufWriter::new(OpenOptions::new().append(true).open(path)?),
+        };
+        Ok((checkpoint, finished))
+    }
+
+    pub fn add(&mut self, tile: &FinishedTile) -> io::Result<()> {
+        self.write_tile(tile)?;
+        self.writer.flush()
+    }
+
+    fn write_tile(&mut self, tile: &FinishedTile) -> io::Result<()> {
+        self.writer.write_all(&(tile.index as u64).to_le_bytes())?;
+        for pixels in [&tile.color, &tile.albedo, &tile.normal] {
+            for pixel in pixels.iter() {
+                for value in [pixel.x, pixel.y, pixel.z] {
+                    self.writer.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn read_tiles<R: BufRead>(
+    mut reader: R,
+    header: &CheckpointHeader,
+    tiles: &[Tile],
+) -> io::Result<Vec<FinishedTile>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    match serde_json::from_str::<CheckpointHeader>(&line) {
+        Ok(file_header) if file_header == *header => {}
+        _ => {
+            warn!("the checkpoint was made for another render, starting over");
+            return Ok(Vec::new());
+        }
+    }
+
+    let mut finished = Vec::new();
+    let mut index_bytes = [0u8; 8];
+    // stops at the end of the file, or at a tile that wasn't fully written
+    while reader.read_exact(&mut index_bytes).is_ok() {
+        let index = u64::from_le_bytes(index_bytes) as usize;
+        let tile = match tiles.get(index) {
+            Some(tile) => tile,
+            None => break,
+        };
+        let mut bytes = vec![0u8; tile.pixel_count() * 3 * 3 * 4];
+        if reader.read_exact(&mut bytes).is_err() {
+            break;
+        }
+
+        let mut pixels = bytes.chunks_exact(12).map(|chunk| {
+            let value = |i: usize| f32::from_le_bytes(chunk[i..i + 4].try_into().unwrap());
+            Pixel::new(value(0), value(4), value(8))
+        });
+        finished.push(FinishedTile {
+            index,
+            color: pixels.by_ref().take(tile.pixel_count()).collect(),
+            albedo: pixels.by_ref().take(tile.pixel_count()).collect(),
+            normal: pixels.collect(),
+        });
+    }
+
+    Ok(finished)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::path::Path;
+
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_split_frame() {
+        for order in [TileOrder::Scanline, TileOrder::Spiral, TileOrder::Hilbert] {
+            let tiles = split_region(&Region::full(100, 50), &TileOptions { size: 32, order });
+
+            assert_eq!(tiles.len(), 8);
+            // every pixel is in exactly one tile
+            let mut covered = vec![0; 100 * 50];
+            for tile in &tiles {
+                for index in tile.pixel_indices(100) {
+                    covered[index] += 1;
+                }
+            }
+            assert!(covered.iter().all(|count| *count == 1));
+        }
+    }
+
+    #[test]
+    fn test_split_region() {
+        let region = Region {
+            x0: 10,
+            y0: 20,
+            x1: 30,
+            y1: 25,
+        };
+        let tiles = split_region(&region, &TileOptions::default());
+
+        assert_eq!(
+            tiles,
+            vec![Tile {
+                x: 10,
+                y: 20,
+                width: 20,
+                height: 5
+            }]
+        );
+    }
+
+    #[test]
+    fn test_hilbert_order() {
+        let options = TileOptions {
+            size: 1,
+            order: TileOrder::Hilbert,
+        };
+        let tiles = split_region(&Region::full(4, 4), &options);
+
+        // consecutive tiles are neighbours
+        for pair in tiles.windows(2) {
+            let distance = (pair[0].x as i32 - pair[1].x as i32).abs()
+                + (pair[0].y as i32 - pair[1].y as i32).abs();
+            assert_eq!(distance, 1);
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let directory = TestDir::new("checkpoint");
+        let path = &directory.file("out.png.checkpoint");
+        let header = CheckpointHeader {
+            width: 4,
+            height: 2,
+            samples_per_pixel: 1,
+            tiles: TileOptions {
+                size: 2,
+                order: TileOrder::Scanline,
+            },
+            region: Region::full(4, 2),
+            scene_hash: 1,
+        };
+        let tiles = split_region(&header.region, &header.tiles);
+        let make_tile = |index: usize| FinishedTile {
+            index,
+            color: vec![Pixel::new(index as f32, 0.5, 1.0); 4],
+            albedo: vec![Pixel::new(0.1, 0.2, 0.3); 4],
+            normal: vec![Pixel::new(0.0, 1.0, 0.0); 4],
+        };
+
+        let (mut checkpoint, finished) = Checkpoint::open(path, &header, &tiles).unwrap();
+        assert!(finished.is_empty());
+        checkpoint.add(&make_tile(1)).unwrap();
+        drop(checkpoint);
+
+        // a tile cut short by an interruption is ignored
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(&0u64.to_le_bytes()).unwrap();
+        file.write_all(&[0u8; 10]).unwrap();
+        drop(file);
+
+        let (_, finished) = Checkpoint::open(path, &header, &tiles).unwrap();
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].index, 1);
+        assert_eq!(finished[0].color[3], Pixel::new(1.0, 0.5, 1.0));
+        assert_eq!(finished[0].normal[0], Pixel::new(0.0, 1.0, 0.0));
+
+        // a different render starts over
+        let other_header = CheckpointHeader {
+            samples_per_pixel: 4,
+            ..header
+        };
+        let (_, finished) = Checkpoint::open(path, &other_header, &tiles).unwrap();
+        assert!(finished.is_empty());
+        let (mut checkpoint, _) = Checkpoint::open(path, &header, &tiles).unwrap();
+        checkpoint.add(&make_tile(0)).unwrap();
+        drop(checkpoint);
+        let changed_scene = CheckpointHeader {
+            scene_hash: 2,
+            ..header
+        };
+        let (_, finished) = Checkpoint::open(path, &changed_scene, &tiles).unwrap();
+        assert!(finished.is_empty());
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+    }
+}
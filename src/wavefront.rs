@@ -1,58 +1,189 @@
 use std::fs;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
 
-use cgmath::Vector3;
+use cgmath::{Vector2, Vector3};
+use serde::{Deserialize, Serialize};
+
+use crate::mtl::{self, MtlMaterial};
+use crate::shapes::material::Color;
+use crate::{ply, stl};
 
 // a simple wavefront obj parser
+// it is also the mesh read from the other formats, their faces are counter clockwise and indexed from 1 as well
+#[derive(Default, Serialize, Deserialize)]
 pub struct Obj {
     pub vertexes: Vec<Vector3<f32>>,
     pub faces: Vec<Vector3<usize>>,
+    // one per vertex, or empty
+    pub normals: Vec<Vector3<f32>>,
+    pub colors: Vec<Color>,
+    // the texture coordinates of the corners of each face, or empty
+    pub uvs: Vec<[Vector2<f32>; 3]>,
+    // the index in materials of the usemtl of each face, or empty
+    pub face_materials: Vec<Option<usize>>,
+    pub materials: Vec<MtlMaterial>,
+    // the mtllib files, relative to the obj file
+    pub libraries: Vec<String>,
+}
+
+// reads an obj, ply or stl file depending on its extension
+pub fn read_mesh_file(file_name: &str) -> Result<Obj, Error> {
+    let extension = Path::new(file_name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("ply") => ply::read(&fs::read(file_name)?),
+        Some("stl") => stl::read(&fs::read(file_name)?),
+        _ => Obj::from_file(file_name),
+    }
+}
+
+fn invalid(line: usize, message: &str) -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        format!("invalid obj file: line {}: {}", line, message),
+    )
+}
+
+fn number<T: FromStr>(line: usize, word: &str) -> Result<T, Error> {
+    word.parse()
+        .map_err(|_| invalid(line, &format!("{} is not a number", word)))
+}
+
+// a 1-based index, or a negative one counting back from the last of the count elements
+fn index(line: usize, word: &str, count: usize, what: &str) -> Result<usize, Error> {
+    let index: isize = number(line, word)?;
+    let index = if index < 0 {
+        count as isize + index + 1
+    } else {
+        index
+    };
+    if index < 1 || index as usize > count {
+        return Err(invalid(
+            line,
+            &format!("{} index {} out of range, there are {}", what, word, count),
+        ));
+    }
+    Ok(index as usize)
 }
 
 impl Obj {
-    pub fn from_file(file_name: &str) -> Result<Obj, std::io::Error> {
+    // the mtllib files are read next to the obj file
+    pub fn from_file(file_name: &str) -> Result<Obj, Error> {
         let contents = fs::read_to_string(file_name)?;
-        Ok(Obj::from_string(&contents))
+        let mut obj = Obj::from_string(&contents)?;
+        obj.load_materials(
+            Path::new(file_name)
+                .parent()
+                .unwrap_or_else(|| Path::new("")),
+        )?;
+        Ok(obj)
     }
 
-    pub fn from_string(buffer: &str) -> Obj {
-        let (vertexes, faces) = buffer
-            .lines()
-            .filter_map(|line| {
-                let words = line.split_ascii_whitespace().collect::<Vec<&str>>();
-                if words.len() == 4 {
-                    match words[0] {
-                        "v" => {
-                            let x = words[1].parse().unwrap();
-                            let y = words[2].parse().unwrap();
-                            let z = words[3].parse().unwrap();
-                            Some((Some(Vector3::new(x, y, z)), None))
-                        }
-                        "f" => {
-                            let x = words[1].parse().unwrap();
-                            let y = words[2].parse().unwrap();
-                            let z = words[3].parse().unwrap();
-                            Some((None, Some(Vector3::new(x, y, z))))
-                        }
-                        _ => None,
-                    }
-                } else {
-                    None
+    // reads the mtllib files from directory, and replaces the materials used by the faces by the ones of the same name
+    pub fn load_materials(&mut self, directory: &Path) -> Result<(), Error> {
+        for library in &self.libraries {
+            let library = directory.join(library);
+            let contents = fs::read_to_string(&library)
+                .map_err(|err| Error::new(err.kind(), format!("{}: {}", library.display(), err)))?;
+            for material in mtl::read(&contents, directory)? {
+                if let Some(used) = self
+                    .materials
+                    .iter_mut()
+                    .find(|used| used.name == material.name)
+                {
+                    *used = material;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn from_string(buffer: &str) -> Result<Obj, Error> {
+        let mut obj = Obj::default();
+        let mut texture_coordinates: Vec<Vector2<f32>> = Vec::new();
+        let mut uvs = Vec::new();
+        let mut face_materials = Vec::new();
+        let mut material = None;
+
+        for (line_number, line) in buffer.lines().enumerate() {
+            let line_number = line_number + 1;
+            let number = |word: &str| number::<f32>(line_number, word);
+            let words: Vec<&str> = line.split_ascii_whitespace().collect();
+            match words.as_slice() {
+                ["v", x, y, z, ..] => {
+                    obj.vertexes
+                        .push(Vector3::new(number(x)?, number(y)?, number(z)?))
+                }
+                // the textures are stored from their top row
+                ["vt", u, v, ..] => {
+                    texture_coordinates.push(Vector2::new(number(u)?, 1.0 - number(v)?))
+                }
+                ["vt", u] => texture_coordinates.push(Vector2::new(number(u)?, 1.0)),
+                ["mtllib", libraries @ ..] => obj
+                    .libraries
+                    .extend(libraries.iter().map(|library| library.to_string())),
+                ["usemtl", ..] => {
+                    material = Some(mtl::material_index(
+                        &mut obj.materials,
+                        &words[1..].join(" "),
+                    ))
                 }
-            })
-            .fold(
-                (Vec::new(), Vec::new()),
-                |(mut acc_v, mut acc_f), (elem_v, elem_f)| {
-                    if let Some(elem_v) = elem_v {
-                        acc_v.push(elem_v);
-                        (acc_v, acc_f)
-                    } else {
-                        acc_f.push(elem_f.unwrap());
-                        (acc_v, acc_f)
+                ["f", corners @ ..] if corners.len() >= 3 => {
+                    // v, v/vt, v//vn or v/vt/vn
+                    let mut face = Vec::with_capacity(corners.len());
+                    for corner in corners {
+                        let mut indices = corner.split('/');
+                        let vertex = index(
+                            line_number,
+                            indices.next().unwrap_or_default(),
+                            obj.vertexes.len(),
+                            "vertex",
+                        )?;
+                        let uv = match indices.next().filter(|uv| !uv.is_empty()) {
+                            Some(uv) => Some(
+                                texture_coordinates[index(
+                                    line_number,
+                                    uv,
+                                    texture_coordinates.len(),
+                                    "texture coordinate",
+                                )? - 1],
+                            ),
+                            None => None,
+                        };
+                        face.push((vertex, uv));
                     }
-                },
-            );
 
-        Obj { vertexes, faces }
+                    // a fan of triangles
+                    for i in 1..face.len() - 1 {
+                        let triangle = [face[0], face[i], face[i + 1]];
+                        obj.faces
+                            .push(Vector3::new(triangle[0].0, triangle[1].0, triangle[2].0));
+                        uvs.push(triangle.map(|(_, uv)| uv));
+                        face_materials.push(material);
+                    }
+                }
+                ["v", ..] | ["vt", ..] | ["f", ..] => {
+                    return Err(invalid(line_number, "missing values"))
+                }
+                _ => {}
+            }
+        }
+
+        // faces without texture coordinates get the corner of the texture
+        if uvs.iter().flatten().any(Option::is_some) {
+            obj.uvs = uvs
+                .into_iter()
+                .map(|uvs| uvs.map(|uv| uv.unwrap_or_else(|| Vector2::new(0.0, 0.0))))
+                .collect();
+        }
+        if !obj.materials.is_empty() {
+            obj.face_materials = face_materials;
+        }
+        Ok(obj)
     }
 }
 
@@ -60,6 +191,8 @@ impl Obj {
 mod test {
     use super::*;
 
+    use crate::test_dir::TestDir;
+
     #[test]
     fn test_obj_from_string() {
         let obj = Obj::from_string(
@@ -68,10 +201,11 @@ mod test {
         v 5.0 -4.0 -9.0 
         v 5.0 -4.0 -8.0 
         f 1 3 2 
-        f 2 4 1 
-        f 4 5 1 
+        f 2 -1 1 
+        f 3 1 2 
         "#,
-        );
+        )
+        .unwrap();
 
         assert_eq!(
             obj.vertexes,
@@ -85,9 +219,78 @@ mod test {
             obj.faces,
             vec![
                 Vector3::new(1, 3, 2),
-                Vector3::new(2, 4, 1),
-                Vector3::new(4, 5, 1)
+                Vector3::new(2, 3, 1),
+                Vector3::new(3, 1, 2)
             ]
         );
     }
+
+    #[test]
+    fn test_invalid_obj() {
+        let vertexes = "v 0 0 0\nv 1 0 0\nv 0 1 0\n";
+        for face in [
+            "f 1 2 9",
+            "f 0 1 2",
+            "f -4 1 2",
+            "f 1/1 2 3",
+            "f 1 2 x",
+            "v 1 2",
+        ] {
+            let err = Obj::from_string(&format!("{}{}\n", vertexes, face)).err();
+            assert_eq!(
+                err.map(|err| err.kind()),
+                Some(ErrorKind::InvalidData),
+                "{}",
+                face
+            );
+        }
+        assert!(Obj::from_string("v 0 0 zero\n").is_err());
+    }
+
+    #[test]
+    fn test_obj_with_materials() {
+        let directory = TestDir::new("mtl");
+        let mut texture = image::RgbImage::new(2, 1);
+        texture.put_pixel(1, 0, image::Rgb([255, 255, 255]));
+        texture.save(directory.join("texture.png")).unwrap();
+        fs::write(
+            directory.join("square.mtl"),
+            "newmtl red\nKd 1 0 0\n\nnewmtl textured\nmap_Kd texture.png\n",
+        )
+        .unwrap();
+        fs::write(
+            directory.join("square.obj"),
+            "mtllib square.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 1
+f 1 2 3
+usemtl red
+f 1 3 4
+usemtl textured
+f -4/1 -3/2 -2/2 -1/1
+",
+        )
+        .unwrap();
+
+        let obj = Obj::from_file(directory.join("square.obj").to_str().unwrap()).unwrap();
+
+        // the quad is split in two triangles
+        assert_eq!(obj.faces.len(), 4);
+        assert_eq!(obj.faces[3], Vector3::new(1, 3, 4));
+        assert_eq!(obj.face_materials, vec![None, Some(0), Some(1), Some(1)]);
+        assert_eq!(obj.materials[0].diffuse, Color::new(1.0, 0.0, 0.0));
+        assert!(obj.materials[1].diffuse_map.is_some());
+        // flipped vertically, the faces without vt are at the corner
+        assert_eq!(obj.uvs[2][0], Vector2::new(0.0, 1.0));
+        assert_eq!(obj.uvs[2][1], Vector2::new(1.0, 0.0));
+        assert_eq!(obj.uvs[0][0], Vector2::new(0.0, 0.0));
+
+        // a missing library
+        fs::write(directory.join("missing.obj"), "mtllib missing.mtl\n").unwrap();
+        assert!(Obj::from_file(directory.join("missing.obj").to_str().unwrap()).is_err());
+    }
 }